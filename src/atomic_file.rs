@@ -0,0 +1,112 @@
+//! Atomic replacement of the files awsaml writes, which hold the credentials and the assertions.
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Replace the content of `path` with `contents`.
+///
+/// A temporary file in the same directory is written, synced and renamed over `path`,
+/// so that the readers which do not take the awsaml locks, such as the AWS CLI and the SDKs,
+/// see either the old content or the new one, never a half-written file.
+/// A new file is readable only by the user, and an existing one keeps its permissions.
+pub(crate) fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // replace the file a symlink points to, not the symlink itself
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let tmp_path = tmp_path_of(&path)?;
+    let result = write_and_rename(&path, &tmp_path, contents);
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// `.<name>.<uuid>.tmp` next to `path`
+fn tmp_path_of(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("No file name in {}", path.display()),
+    ))?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".{}.tmp", Uuid::new_v4()));
+    Ok(path.with_file_name(tmp_name))
+}
+
+fn write_and_rename(path: &Path, tmp_path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(tmp_path)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(tmp_path, path)?;
+    // make the rename itself durable, which is best effort
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().and_then(|dir| File::open(dir).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("awsaml-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_write_new() {
+        //Arrange
+        let dir = temp_dir();
+        let path = dir.join("credentials");
+        //Act
+        write(&path, b"[default]\n").unwrap();
+        //Assert
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[default]\n");
+        // only the written file is left
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_existing() {
+        use std::os::unix::fs::PermissionsExt;
+        //Arrange
+        let dir = temp_dir();
+        let path = dir.join("config");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        //Act
+        write(&link, b"new").unwrap();
+        //Assert
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,60 @@
+use std::path::{Path, PathBuf};
+
 mod assume_role;
+mod caller_identity;
 mod config;
 pub mod credentials;
+mod ini_file;
+mod partition;
 
-pub use assume_role::assume_role_with_saml;
-pub use config::Config;
+pub use assume_role::{assume_role, assume_role_with_saml, ChainedRole, StsSettings};
+pub use caller_identity::{get_caller_identity, CallerIdentity};
+pub use config::{Config, SectionName};
+pub use credentials::{CredentialSink, Credentials, CredentialsFile};
+pub use ini_file::{IniFile, Section, SectionMut};
+pub use partition::Partition;
 
 pub const AWS_SAML_CALLBACK: &str = "https://signin.aws.amazon.com/saml";
+
+/// The directory where awsaml keeps its own state, next to the credentials file
+pub fn state_dir() -> PathBuf {
+    state_dir_beside(&credentials::file_path())
+}
+
+/// The directory where awsaml keeps its own state next to the given credentials file
+pub fn state_dir_beside(credentials_file: &str) -> PathBuf {
+    Path::new(credentials_file)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("awsaml")
+}
+
+/// The profile name usable as a part of a file name in the state directory.
+/// The other characters than `[A-Za-z0-9-_.]` are percent-encoded, `%` included,
+/// so that different profiles, such as `a/b` and `a_b`, never share a file.
+pub fn file_name_of(profile: &str) -> String {
+    let mut file_name = String::with_capacity(profile.len());
+    for byte in profile.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                file_name.push(byte as char)
+            }
+            _ => file_name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    file_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name_of() {
+        assert_eq!(file_name_of("dev-1.prod_2"), "dev-1.prod_2");
+        assert_eq!(file_name_of("a/b"), "a%2Fb");
+        assert_eq!(file_name_of("a%2Fb"), "a%252Fb");
+        assert_eq!(file_name_of("a b\\é"), "a%20b%5C%C3%A9");
+        assert_ne!(file_name_of("a/b"), file_name_of("a_b"));
+    }
+}
@@ -1,6 +1,330 @@
-use crate::saml::SamlResponse;
+use std::time::{Duration, SystemTime};
 
-pub fn assume_role_with_saml(_saml_response: SamlResponse) {
-    // AssumeRoleWithSAML
-    unimplemented!()
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_sts::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_sts::types::{AssumedRoleUser, Tag};
+use chrono::DateTime;
+
+use super::credentials::Credentials;
+use super::partition::Partition;
+use crate::saml::{SamlResponse, SamlRole, SessionTag};
+use crate::{Error, Result};
+
+/// The shortest maximum session duration of a role
+const MIN_MAX_SESSION_DURATION: Duration = Duration::from_secs(3600);
+/// The longest maximum session duration of a role
+const MAX_MAX_SESSION_DURATION: Duration = Duration::from_secs(12 * 3600);
+/// The requests of AssumeRoleWithSAML, enough to step down from 12 hours to 1 hour
+const MAX_DURATION_ATTEMPTS: usize = 12;
+
+/// How to call STS.
+/// The regional endpoint of `region` is called, `sts.<region>.amazonaws.com` for example.
+#[derive(Debug, Clone, Default)]
+pub struct StsSettings {
+    /// The region of the STS endpoint, resolved from the environment if `None`.
+    /// The default region of the partition is taken instead
+    /// when none is found there or the one found is in another partition.
+    pub region: Option<String>,
+    pub partition: Partition,
+    /// Call the FIPS endpoint of the region, such as `sts-fips.us-east-1.amazonaws.com`
+    pub use_fips: bool,
+    /// Call this endpoint instead of the one of the region, e.g. a VPC endpoint
+    pub endpoint_url: Option<String>,
+}
+
+impl StsSettings {
+    /// Check that the region is in the partition, which has the FIPS endpoints if required
+    pub fn validate(&self) -> Result<()> {
+        if let Some(region) = &self.region {
+            if Partition::of_region(region) != self.partition {
+                return Err(Error::Config(format!(
+                    "Region {} is not in the partition {}",
+                    region, self.partition
+                )));
+            }
+        }
+        if self.use_fips && !self.partition.has_fips() {
+            return Err(Error::Config(format!(
+                "The partition {} has no FIPS endpoints",
+                self.partition
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A role assumed with the credentials of another role
+#[derive(Debug, Clone)]
+pub struct ChainedRole {
+    pub role_arn: String,
+    pub role_session_name: String,
+    /// Required by the trust policy of the role, if any
+    pub external_id: Option<String>,
+    /// The role's default (1 hour) if `None`
+    pub duration_seconds: Option<i32>,
+    /// Added to the transitive tags of the source session
+    pub session_tags: Vec<SessionTag>,
+    /// The keys of `session_tags` which persist into further chained roles
+    pub transitive_tag_keys: Vec<String>,
+}
+
+/// Exchange the SAML assertion for the temporary credentials of the role.
+///
+/// `duration` is capped by the `SessionDuration` of the assertion.
+/// When it exceeds the maximum session duration of the role,
+/// the request is retried with shorter durations by the hour.
+pub async fn assume_role_with_saml(
+    saml_response: &SamlResponse,
+    role: &SamlRole,
+    duration: Option<Duration>,
+    sts: &StsSettings,
+) -> Result<Credentials> {
+    // AssumeRoleWithSAML is authenticated by the assertion, not by credentials
+    let client = sts_client(sts, None).await;
+    let saml_assertion = saml_response.to_base64()?;
+    let mut duration = match (duration, saml_response.session_duration()?) {
+        (Some(duration), Some(max)) if max < duration => {
+            log::info!("The session duration is capped to {:?} by the IdP", max);
+            Some(max)
+        }
+        (duration, _) => duration,
+    }
+    .map(cap_duration);
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        log::debug!("AssumeRoleWithSAML: {:?} for {:?}", role, duration);
+        let result = client
+            .assume_role_with_saml()
+            .role_arn(&role.role_arn)
+            .principal_arn(&role.principal_arn)
+            .saml_assertion(&saml_assertion)
+            .set_duration_seconds(duration.map(duration_seconds).transpose()?)
+            .send()
+            .await;
+        match result {
+            Ok(output) => {
+                return into_credentials(
+                    output.credentials,
+                    output.assumed_role_user,
+                    output.source_identity,
+                )
+            }
+            Err(e) if exceeds_max_session_duration(&e) && attempts < MAX_DURATION_ATTEMPTS => {
+                match duration.and_then(|d| shorter_duration(d).map(|shorter| (d, shorter))) {
+                    Some((d, shorter)) => {
+                        log::warn!(
+                            "{:?} exceeds the maximum session duration of {}, retrying with {:?}",
+                            d,
+                            role.role_arn,
+                            shorter
+                        );
+                        duration = Some(shorter);
+                    }
+                    None => return Err(Error::Sts(DisplayErrorContext(e).to_string())),
+                }
+            }
+            Err(e) => return Err(Error::Sts(DisplayErrorContext(e).to_string())),
+        }
+    }
+}
+
+/// The duration capped by the longest maximum session duration of a role.
+/// A shorter one is kept, as it may be capped by the IdP.
+fn cap_duration(duration: Duration) -> Duration {
+    duration.min(MAX_MAX_SESSION_DURATION)
+}
+
+/// The next duration to try, the whole hour below `duration`,
+/// or `None` if it is already the shortest maximum session duration
+fn shorter_duration(duration: Duration) -> Option<Duration> {
+    if duration <= MIN_MAX_SESSION_DURATION {
+        return None;
+    }
+    Some(Duration::from_secs((duration.as_secs() - 1) / 3600 * 3600))
+}
+
+/// The DurationSeconds of the request
+fn duration_seconds(duration: Duration) -> Result<i32> {
+    i32::try_from(duration.as_secs())
+        .map_err(|_| Error::Config(format!("Invalid session duration: {:?}", duration)))
+}
+
+/// Whether STS rejected the DurationSeconds exceeding the MaxSessionDuration of the role
+fn exceeds_max_session_duration<E: ProvideErrorMetadata, R>(e: &SdkError<E, R>) -> bool {
+    e.as_service_error().is_some_and(|e| {
+        e.code() == Some("ValidationError")
+            && e.message()
+                .is_some_and(|message| message.contains("MaxSessionDuration"))
+    })
+}
+
+/// Assume the role with the credentials of another role, e.g. the one assumed with SAML
+pub async fn assume_role(
+    source: &Credentials,
+    role: &ChainedRole,
+    sts: &StsSettings,
+) -> Result<Credentials> {
+    let client = sts_client(sts, Some(source)).await;
+    let tags = role
+        .session_tags
+        .iter()
+        .map(|tag| {
+            Tag::builder()
+                .key(&tag.key)
+                .value(&tag.value)
+                .build()
+                .map_err(|e| Error::Config(format!("Invalid session tag {}: {}", tag.key, e)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    log::debug!("AssumeRole: {:?}", role);
+    let output = client
+        .assume_role()
+        .role_arn(&role.role_arn)
+        .role_session_name(&role.role_session_name)
+        .set_external_id(role.external_id.clone())
+        .set_duration_seconds(role.duration_seconds)
+        .set_tags(Some(tags).filter(|tags| !tags.is_empty()))
+        .set_transitive_tag_keys(
+            Some(role.transitive_tag_keys.clone()).filter(|keys| !keys.is_empty()),
+        )
+        .send()
+        .await
+        .map_err(|e| Error::Sts(DisplayErrorContext(e).to_string()))?;
+
+    into_credentials(
+        output.credentials,
+        output.assumed_role_user,
+        output.source_identity,
+    )
+}
+
+/// The STS client signing the requests with `credentials`, or not signing if `None`
+pub(super) async fn sts_client(
+    sts: &StsSettings,
+    credentials: Option<&Credentials>,
+) -> aws_sdk_sts::Client {
+    let region = match &sts.region {
+        Some(region) => Region::new(region.clone()),
+        None => sts_region(
+            sts.partition,
+            RegionProviderChain::default_provider().region().await,
+        ),
+    };
+    let mut loader = aws_config::defaults(BehaviorVersion::latest())
+        .region(region)
+        .use_fips(sts.use_fips);
+    loader = match credentials {
+        Some(credentials) => loader.credentials_provider(aws_sdk_sts::config::Credentials::new(
+            &credentials.access_key_id,
+            &credentials.secret_access_key,
+            Some(credentials.session_token.clone()),
+            Some(SystemTime::from(credentials.expiration)),
+            "awsaml",
+        )),
+        None => loader.no_credentials(),
+    };
+    if let Some(endpoint_url) = &sts.endpoint_url {
+        loader = loader.endpoint_url(endpoint_url);
+    }
+    let config = loader.load().await;
+    aws_sdk_sts::Client::new(&config)
+}
+
+/// The region resolved from the environment if it is in the partition,
+/// so that e.g. `AWS_REGION=us-east-1` does not send a GovCloud profile to the commercial STS
+fn sts_region(partition: Partition, resolved: Option<Region>) -> Region {
+    match resolved {
+        Some(region) if Partition::of_region(region.as_ref()) == partition => region,
+        Some(region) => {
+            log::debug!(
+                "Region {} is not in the partition {}, calling STS in {}",
+                region,
+                partition,
+                partition.default_region()
+            );
+            Region::new(partition.default_region())
+        }
+        None => Region::new(partition.default_region()),
+    }
+}
+
+fn into_credentials(
+    credentials: Option<aws_sdk_sts::types::Credentials>,
+    assumed_role_user: Option<AssumedRoleUser>,
+    source_identity: Option<String>,
+) -> Result<Credentials> {
+    let credentials =
+        credentials.ok_or(Error::Sts("No credentials in the response".to_string()))?;
+    Ok(Credentials {
+        access_key_id: credentials.access_key_id,
+        secret_access_key: credentials.secret_access_key,
+        session_token: credentials.session_token,
+        expiration: DateTime::from_timestamp(credentials.expiration.secs(), 0)
+            .ok_or(Error::Sts("Invalid expiration".to_string()))?,
+        assumed_role_arn: assumed_role_user.map(|user| user.arn),
+        source_identity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cap_duration() {
+        let hours = |h: u64| Duration::from_secs(h * 3600);
+        assert_eq!(
+            cap_duration(Duration::from_secs(900)),
+            Duration::from_secs(900)
+        );
+        assert_eq!(cap_duration(hours(6)), hours(6));
+        assert_eq!(cap_duration(hours(240)), hours(12));
+    }
+
+    #[test]
+    fn test_shorter_duration() {
+        //Arrange
+        let hours = |h: u64| Duration::from_secs(h * 3600);
+        //Act
+        let steps =
+            std::iter::successors(Some(hours(12)), |d| shorter_duration(*d)).collect::<Vec<_>>();
+        //Assert
+        assert_eq!(steps, (1..=12).rev().map(hours).collect::<Vec<_>>());
+        assert!(steps.len() <= MAX_DURATION_ATTEMPTS);
+        assert_eq!(shorter_duration(Duration::from_secs(5400)), Some(hours(1)));
+        assert_eq!(shorter_duration(hours(1)), None);
+    }
+
+    #[test]
+    fn test_duration_seconds() {
+        assert_eq!(duration_seconds(Duration::from_secs(43200)).unwrap(), 43200);
+        assert!(matches!(
+            duration_seconds(Duration::from_secs(u64::MAX)),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_sts_region() {
+        //Arrange
+        let us_east_1 = || Some(Region::new("us-east-1"));
+        //Act & Assert
+        assert_eq!(
+            sts_region(Partition::Aws, us_east_1()).as_ref(),
+            "us-east-1"
+        );
+        assert_eq!(
+            sts_region(Partition::AwsUsGov, us_east_1()).as_ref(),
+            "us-gov-west-1"
+        );
+        assert_eq!(
+            sts_region(Partition::AwsCn, Some(Region::new("cn-northwest-1"))).as_ref(),
+            "cn-northwest-1"
+        );
+        assert_eq!(sts_region(Partition::AwsCn, None).as_ref(), "cn-north-1");
+    }
 }
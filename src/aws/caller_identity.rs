@@ -0,0 +1,31 @@
+use aws_sdk_sts::error::DisplayErrorContext;
+
+use super::assume_role::{sts_client, StsSettings};
+use super::credentials::Credentials;
+use crate::{Error, Result};
+
+/// The identity of the credentials as seen by AWS
+#[derive(Debug, Clone)]
+pub struct CallerIdentity {
+    pub account: String,
+    pub arn: String,
+    pub user_id: String,
+}
+
+/// Ask STS whose the credentials are, which fails if they are expired or revoked
+pub async fn get_caller_identity(
+    credentials: &Credentials,
+    sts: &StsSettings,
+) -> Result<CallerIdentity> {
+    let client = sts_client(sts, Some(credentials)).await;
+    let output = client
+        .get_caller_identity()
+        .send()
+        .await
+        .map_err(|e| Error::Sts(DisplayErrorContext(e).to_string()))?;
+    Ok(CallerIdentity {
+        account: output.account.unwrap_or_default(),
+        arn: output.arn.unwrap_or_default(),
+        user_id: output.user_id.unwrap_or_default(),
+    })
+}
@@ -1,8 +1,10 @@
 //! This module provides a struct to manage AWS config file.
-use anyhow::Result;
-use std::{env, path::Path};
+use std::{env, fmt, path::Path};
 
-use ini::Ini;
+use super::ini_file::{IniFile, Section, SectionMut};
+use crate::lock::FileLock;
+
+use crate::{Error, Result};
 
 fn file_path() -> String {
     env::var("AWS_CONFIG_FILE").unwrap_or_else(|_| {
@@ -14,31 +16,174 @@ fn file_path() -> String {
     })
 }
 
+/// Names of the sections of the config file.
+/// Unlike the credentials file, where a section is just named after the profile,
+/// the profiles except `default` are prefixed with `profile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SectionName {
+    /// `[default]`
+    Default,
+    /// `[profile name]`
+    Profile(String),
+    /// `[sso-session name]`
+    SsoSession(String),
+    /// `[awsaml-session name]`, the values shared by the profiles referring to it
+    AwsamlSession(String),
+}
+
+impl SectionName {
+    /// The section of the profile
+    pub fn profile(profile: &str) -> Self {
+        if profile == "default" {
+            SectionName::Default
+        } else {
+            SectionName::Profile(profile.to_string())
+        }
+    }
+
+    /// Parse the name in the brackets, `None` for unknown kinds of sections
+    pub fn parse(section: &str) -> Option<Self> {
+        let section = section.trim();
+        if section == "default" {
+            return Some(SectionName::Default);
+        }
+        let (kind, name) = section.split_once(char::is_whitespace)?;
+        let name = name.trim().to_string();
+        match kind {
+            "profile" => Some(SectionName::Profile(name)),
+            "sso-session" => Some(SectionName::SsoSession(name)),
+            "awsaml-session" => Some(SectionName::AwsamlSession(name)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SectionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SectionName::Default => write!(f, "default"),
+            SectionName::Profile(name) => write!(f, "profile {}", name),
+            SectionName::SsoSession(name) => write!(f, "sso-session {}", name),
+            SectionName::AwsamlSession(name) => write!(f, "awsaml-session {}", name),
+        }
+    }
+}
+
 pub struct Config {
     pub file_path: String,
-    pub ini: Ini,
+    pub ini: IniFile,
+    /// Held from the load until dropped, so that the read-modify-write is not interleaved
+    lock: Option<FileLock>,
 }
 
 impl Config {
+    /// Load the config file to be modified and saved, an empty one if it does not exist.
+    /// The exclusive lock keeps the other awsaml processes out until dropped.
     pub fn load_or_new() -> Result<Self> {
         let file_path = file_path();
+        let lock = Some(FileLock::acquire(&file_path)?);
         // the file does not exist
         if Path::new(&file_path).exists() {
-            let ini = Ini::load_from_file(&file_path)?;
-            Ok(Config { file_path, ini })
+            let ini = IniFile::load(&file_path)
+                .map_err(|e| Error::Config(format!("{}: {}", &file_path, e)))?;
+            Ok(Config {
+                file_path,
+                ini,
+                lock,
+            })
         } else {
             println!("{} not found, so creating a new one", &file_path);
             Ok(Config {
                 file_path,
-                ini: Ini::new(),
+                ini: IniFile::new(),
+                lock,
             })
         }
     }
 
+    /// Load the config file only to read it, an empty one if it does not exist.
+    /// The shared lock lets the other readers in, and cannot be saved.
+    pub fn load() -> Result<Self> {
+        let file_path = file_path();
+        let lock = Some(FileLock::acquire_shared(&file_path)?);
+        let ini = if Path::new(&file_path).exists() {
+            IniFile::load(&file_path)
+                .map_err(|e| Error::Config(format!("{}: {}", &file_path, e)))?
+        } else {
+            IniFile::new()
+        };
+        Ok(Config {
+            file_path,
+            ini,
+            lock,
+        })
+    }
+
     pub fn save(&self) -> Result<()> {
-        self.ini.write_to_file(&self.file_path)?;
+        debug_assert!(
+            self.lock.as_ref().is_some_and(FileLock::is_exclusive),
+            "saving {} without the exclusive lock",
+            self.file_path
+        );
+        self.ini.save(&self.file_path)?;
         Ok(())
     }
+
+    /// The section of the profile.
+    /// Besides `[profile name]`, the legacy `[name]` written by the former awsaml is read.
+    pub fn profile(&self, profile: &str) -> Option<Section<'_>> {
+        self.ini
+            .section(&SectionName::profile(profile).to_string())
+            .or_else(|| self.ini.section(profile))
+    }
+
+    /// The `[awsaml-session name]` section
+    pub fn awsaml_session(&self, name: &str) -> Option<Section<'_>> {
+        self.ini
+            .section(&SectionName::AwsamlSession(name.to_string()).to_string())
+    }
+
+    /// The `[awsaml-session name]` section to be written
+    pub fn awsaml_session_mut(&mut self, name: &str) -> SectionMut<'_> {
+        self.ini
+            .with_section(&SectionName::AwsamlSession(name.to_string()).to_string())
+    }
+
+    /// The section of the profile to be written.
+    /// A legacy `[name]` section having any of `owned_keys` is renamed in place,
+    /// or merged into the correct section if both exist, where the values of the correct one win.
+    pub fn profile_mut(&mut self, profile: &str, owned_keys: &[&str]) -> SectionMut<'_> {
+        let section_name = SectionName::profile(profile).to_string();
+        let legacy = (section_name != profile)
+            .then(|| self.ini.section(profile))
+            .flatten()
+            .filter(|section| owned_keys.iter().any(|key| section.contains_key(key)))
+            .map(|section| {
+                section
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<Vec<_>>()
+            });
+
+        if let Some(legacy) = legacy {
+            log::info!("Migrating [{}] to [{}]", profile, section_name);
+            if self.ini.section(&section_name).is_none() {
+                self.ini.rename_section(profile, &section_name);
+            } else {
+                for (key, value) in legacy {
+                    if !self
+                        .ini
+                        .section(&section_name)
+                        .is_some_and(|s| s.contains_key(&key))
+                    {
+                        self.ini.set(&section_name, &key, &value);
+                    }
+                }
+                self.ini.remove_section(profile);
+            }
+        }
+        self.ini.with_section(&section_name)
+    }
 }
 
 #[cfg(test)]
@@ -50,4 +195,82 @@ mod tests {
         let file = file_path();
         assert_eq!(file, "/home/newgyu/.aws/config");
     }
+
+    fn config(content: &str) -> Config {
+        Config {
+            file_path: String::new(),
+            ini: IniFile::parse(content),
+            lock: None,
+        }
+    }
+
+    #[test]
+    fn test_section_name() {
+        assert_eq!(SectionName::profile("default").to_string(), "default");
+        assert_eq!(SectionName::profile("prod").to_string(), "profile prod");
+        assert_eq!(
+            SectionName::parse("sso-session corp"),
+            Some(SectionName::SsoSession("corp".to_string()))
+        );
+        assert_eq!(
+            SectionName::parse("awsaml-session corp"),
+            Some(SectionName::AwsamlSession("corp".to_string()))
+        );
+        assert_eq!(SectionName::parse("prod"), None);
+    }
+
+    #[test]
+    fn test_profile_reads_legacy() {
+        //Arrange
+        let target = config("[prod]\napp_id_uri = legacy\n[profile dev]\napp_id_uri = dev\n");
+        //Act & Assert
+        assert_eq!(
+            target.profile("prod").unwrap().get("app_id_uri"),
+            Some("legacy")
+        );
+        assert_eq!(
+            target.profile("dev").unwrap().get("app_id_uri"),
+            Some("dev")
+        );
+        assert!(target.profile("default").is_none());
+    }
+
+    #[test]
+    fn test_profile_mut_migrates_legacy() {
+        //Arrange
+        let mut target = config(
+            "[prod]\napp_id_uri = legacy\nregion = us-east-1\n[profile prod]\napp_id_uri = new\n[other]\nregion = eu-west-1\n",
+        );
+        //Act
+        target
+            .profile_mut("prod", &["app_id_uri"])
+            .set("entra_id_tenant", "contoso.onmicrosoft.com");
+        target.profile_mut("other", &["app_id_uri"]);
+        //Assert
+        assert!(target.ini.section("prod").is_none());
+        let section = target.ini.section("profile prod").unwrap();
+        assert_eq!(section.get("app_id_uri"), Some("new"));
+        assert_eq!(section.get("region"), Some("us-east-1"));
+        assert_eq!(
+            section.get("entra_id_tenant"),
+            Some("contoso.onmicrosoft.com")
+        );
+        // not written by awsaml
+        assert!(target.ini.section("other").is_some());
+    }
+
+    #[test]
+    fn test_profile_mut_renames_legacy() {
+        //Arrange
+        let mut target = config("# prod account\n[prod]\napp_id_uri = legacy\n");
+        //Act
+        target
+            .profile_mut("prod", &["app_id_uri"])
+            .set("idp", "entra-id");
+        //Assert
+        assert_eq!(
+            target.ini.to_string(),
+            "# prod account\n[profile prod]\napp_id_uri = legacy\nidp = entra-id\n"
+        );
+    }
 }
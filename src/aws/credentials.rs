@@ -1,4 +1,12 @@
-use std::env;
+//! This module provides the temporary credentials and the AWS credentials file.
+use std::{env, path::Path};
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+
+use super::ini_file::{IniFile, Section};
+use crate::lock::FileLock;
+use crate::saml::SamlRole;
+use crate::{Error, Result};
 
 pub fn file_path() -> String {
     env::var("AWS_SHARED_CREDENTIALS_FILE").unwrap_or_else(|_| {
@@ -10,6 +18,256 @@ pub fn file_path() -> String {
     })
 }
 
+/// Temporary credentials issued by STS
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    pub expiration: DateTime<Utc>,
+    /// The ARN of the role session,
+    /// e.g. `arn:aws:sts::123456789012:assumed-role/Admin/user@example.com`
+    pub assumed_role_arn: Option<String>,
+    /// The identity of the user set by the IdP, which stays through the chained roles
+    pub source_identity: Option<String>,
+}
+
+impl Credentials {
+    /// Save the credentials into the profile of the credentials file,
+    /// keeping the other profiles as they are.
+    pub fn save(&self, profile: &str) -> Result<()> {
+        self.save_to(&file_path(), profile)
+    }
+
+    /// Save the credentials into the profile of the given credentials file
+    pub fn save_to(&self, file_path: &str, profile: &str) -> Result<()> {
+        let _lock = FileLock::acquire(file_path)?;
+        let mut ini = load_file(file_path)?;
+        let mut section = ini.with_section(profile);
+        section
+            .set(keys::ACCESS_KEY_ID, &self.access_key_id)
+            .set(keys::SECRET_ACCESS_KEY, &self.secret_access_key)
+            .set(keys::SESSION_TOKEN, &self.session_token)
+            .set(
+                keys::EXPIRATION,
+                self.expiration.to_rfc3339_opts(SecondsFormat::Secs, true),
+            );
+        // the values of the former session must not be left
+        match &self.assumed_role_arn {
+            Some(arn) => section.set(keys::ASSUMED_ROLE_ARN, arn),
+            None => section.remove(keys::ASSUMED_ROLE_ARN),
+        };
+        match &self.source_identity {
+            Some(source_identity) => section.set(keys::SOURCE_IDENTITY, source_identity),
+            None => section.remove(keys::SOURCE_IDENTITY),
+        };
+        ini.save(file_path)
+            .map_err(|e| Error::CredentialsStore(format!("{}: {}", file_path, e)))
+    }
+
+    /// Load the credentials saved by awsaml into the profile of the credentials file.
+    /// `None` if the profile does not exist or is not written by awsaml.
+    ///
+    /// No lock is taken, as the file is replaced by a rename and never seen half-written,
+    /// so that it is cheap enough for the shell prompt.
+    pub fn load(profile: &str) -> Result<Option<Self>> {
+        Self::load_from(&file_path(), profile)
+    }
+
+    /// Load the credentials of the profile from the given credentials file
+    pub fn load_from(file_path: &str, profile: &str) -> Result<Option<Self>> {
+        let ini = load_file(file_path)?;
+        match ini.section(profile) {
+            Some(section) => Self::from_section(section),
+            None => Ok(None),
+        }
+    }
+
+    /// All the profiles of the credentials file with the credentials saved by awsaml,
+    /// read without a lock as [`Credentials::load`]
+    pub fn load_all() -> Result<Vec<(String, Self)>> {
+        let file_path = file_path();
+        let ini = load_file(&file_path)?;
+        let mut all = Vec::new();
+        for name in ini.section_names() {
+            if let Some(credentials) = ini
+                .section(name)
+                .map(Self::from_section)
+                .transpose()?
+                .flatten()
+            {
+                all.push((name.to_string(), credentials));
+            }
+        }
+        Ok(all)
+    }
+
+    /// `None` if the section is not written by awsaml
+    fn from_section(section: Section) -> Result<Option<Self>> {
+        let (Some(access_key_id), Some(secret_access_key), Some(session_token), Some(expiration)) = (
+            section.get(keys::ACCESS_KEY_ID),
+            section.get(keys::SECRET_ACCESS_KEY),
+            section.get(keys::SESSION_TOKEN),
+            section.get(keys::EXPIRATION),
+        ) else {
+            return Ok(None);
+        };
+        let expiration = DateTime::parse_from_rfc3339(expiration)
+            .map_err(|e| Error::CredentialsStore(format!("{}: {}", keys::EXPIRATION, e)))?;
+        Ok(Some(Credentials {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            session_token: session_token.to_string(),
+            expiration: expiration.with_timezone(&Utc),
+            assumed_role_arn: section.get(keys::ASSUMED_ROLE_ARN).map(|v| v.to_string()),
+            source_identity: section.get(keys::SOURCE_IDENTITY).map(|v| v.to_string()),
+        }))
+    }
+
+    /// Remove the credentials saved by awsaml from the profile of the credentials file,
+    /// and the profile if nothing else is left.
+    /// `false` if the profile has none.
+    pub fn remove(profile: &str) -> Result<bool> {
+        let file_path = file_path();
+        let _lock = FileLock::acquire(&file_path)?;
+        let mut ini = load_file(&file_path)?;
+        if !remove_from(&mut ini, profile)? {
+            return Ok(false);
+        }
+        ini.save(&file_path)
+            .map_err(|e| Error::CredentialsStore(format!("{}: {}", &file_path, e)))?;
+        Ok(true)
+    }
+
+    /// Remove the credentials saved by awsaml from all the profiles,
+    /// and returns the profiles.
+    pub fn remove_all() -> Result<Vec<String>> {
+        let file_path = file_path();
+        let _lock = FileLock::acquire(&file_path)?;
+        let mut ini = load_file(&file_path)?;
+        let names = ini
+            .section_names()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let mut removed = Vec::new();
+        for name in names {
+            if remove_from(&mut ini, &name)? {
+                removed.push(name);
+            }
+        }
+        if !removed.is_empty() {
+            ini.save(&file_path)
+                .map_err(|e| Error::CredentialsStore(format!("{}: {}", &file_path, e)))?;
+        }
+        Ok(removed)
+    }
+
+    /// Whether the credentials are still valid for `duration` from now
+    pub fn is_valid_for(&self, duration: Duration) -> bool {
+        Utc::now() + duration < self.expiration
+    }
+
+    /// The account of the assumed role
+    pub fn account(&self) -> Option<&str> {
+        self.assumed_role_arn
+            .as_deref()
+            .and_then(|arn| arn.split(':').nth(4))
+    }
+
+    /// The ARN of the assumed role, without the path which the session ARN omits
+    pub fn role_arn(&self) -> Option<String> {
+        let arn = self.assumed_role_arn.as_deref()?;
+        let partition = arn.split(':').nth(1)?;
+        let role_name = arn.split('/').nth(1)?;
+        Some(format!(
+            "arn:{}:iam::{}:role/{}",
+            partition,
+            self.account()?,
+            role_name
+        ))
+    }
+
+    /// The name of the role session
+    pub fn session_name(&self) -> Option<&str> {
+        self.assumed_role_arn
+            .as_deref()
+            .and_then(|arn| arn.splitn(3, '/').nth(2))
+    }
+}
+
+/// The credentials file, or an empty one if it does not exist
+fn load_file(file_path: &str) -> Result<IniFile> {
+    if Path::new(file_path).exists() {
+        IniFile::load(file_path)
+            .map_err(|e| Error::CredentialsStore(format!("{}: {}", file_path, e)))
+    } else {
+        Ok(IniFile::new())
+    }
+}
+
+/// Remove the keys written by awsaml from the profile, `false` if it is not written by awsaml
+fn remove_from(ini: &mut IniFile, profile: &str) -> Result<bool> {
+    match ini
+        .section(profile)
+        .map(Credentials::from_section)
+        .transpose()?
+    {
+        Some(Some(_)) => {}
+        _ => return Ok(false),
+    }
+    for key in keys::ALL {
+        ini.remove(profile, key);
+    }
+    if ini
+        .section(profile)
+        .is_some_and(|section| section.iter().next().is_none())
+    {
+        ini.remove_section(profile);
+    }
+    Ok(true)
+}
+
+/// Where the credentials are stored after the login
+pub trait CredentialSink: Send + Sync {
+    fn store(&self, role: &SamlRole, credentials: &Credentials) -> Result<()>;
+}
+
+/// A profile of the AWS credentials file
+#[derive(Debug)]
+pub struct CredentialsFile {
+    profile: String,
+}
+
+impl CredentialsFile {
+    pub fn new(profile: String) -> Self {
+        CredentialsFile { profile }
+    }
+}
+
+impl CredentialSink for CredentialsFile {
+    fn store(&self, _role: &SamlRole, credentials: &Credentials) -> Result<()> {
+        credentials.save(&self.profile)
+    }
+}
+
+mod keys {
+    pub const ACCESS_KEY_ID: &str = "aws_access_key_id";
+    pub const SECRET_ACCESS_KEY: &str = "aws_secret_access_key";
+    pub const SESSION_TOKEN: &str = "aws_session_token";
+    /// not used by AWS, but by awsaml to tell whether to login again
+    pub const EXPIRATION: &str = "awsaml_expiration";
+    pub const ASSUMED_ROLE_ARN: &str = "awsaml_assumed_role_arn";
+    pub const SOURCE_IDENTITY: &str = "awsaml_source_identity";
+    pub const ALL: [&str; 6] = [
+        ACCESS_KEY_ID,
+        SECRET_ACCESS_KEY,
+        SESSION_TOKEN,
+        EXPIRATION,
+        ASSUMED_ROLE_ARN,
+        SOURCE_IDENTITY,
+    ];
+}
+
 #[cfg(test)]
 mod tests {
     #[allow(unused_imports)]
@@ -19,4 +277,65 @@ mod tests {
         let file = file_path();
         assert_eq!(file, "/home/newgyu/.aws/credentials");
     }
+
+    #[test]
+    fn test_from_section() {
+        //Arrange
+        let ini = IniFile::parse(
+            "[default]\naws_access_key_id = AKIA\n\n[dev]\naws_access_key_id = ASIA\naws_secret_access_key = secret\naws_session_token = token\nawsaml_expiration = 2030-01-01T00:00:00Z\nawsaml_assumed_role_arn = arn:aws:sts::123456789012:assumed-role/Admin/user@example.com\n",
+        );
+        //Act
+        let default = Credentials::from_section(ini.section("default").unwrap()).unwrap();
+        let dev = Credentials::from_section(ini.section("dev").unwrap()).unwrap();
+        //Assert
+        assert!(default.is_none());
+        let dev = dev.unwrap();
+        assert_eq!(dev.account(), Some("123456789012"));
+        assert_eq!(
+            dev.role_arn().as_deref(),
+            Some("arn:aws:iam::123456789012:role/Admin")
+        );
+        assert_eq!(dev.session_name(), Some("user@example.com"));
+        assert_eq!(dev.source_identity, None);
+    }
+
+    #[test]
+    fn test_load_from_for_prompt() {
+        //Arrange
+        let dir = std::env::temp_dir().join(format!("awsaml-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("credentials");
+        let profiles = (0..100)
+            .map(|i| format!("[p{}]\naws_access_key_id = ASIA\naws_secret_access_key = secret\naws_session_token = {}\nawsaml_expiration = 2030-01-01T00:00:00Z\n", i, "t".repeat(1000)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::write(&path, profiles).unwrap();
+        let path = path.to_str().unwrap();
+        //Act
+        let credentials = Credentials::load_from(path, "p99").unwrap();
+        //Assert
+        assert!(credentials.is_some());
+        // no lock file is created by the readers
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_from() {
+        //Arrange
+        let mut ini = IniFile::parse(
+            "[default]\naws_access_key_id = AKIA\n\n[dev]\naws_access_key_id = ASIA\naws_secret_access_key = secret\naws_session_token = token\nawsaml_expiration = 2030-01-01T00:00:00Z\n\n[prod]\nregion = eu-west-1\naws_access_key_id = ASIA\naws_secret_access_key = secret\naws_session_token = token\nawsaml_expiration = 2030-01-01T00:00:00Z\n",
+        );
+        //Act
+        let default = remove_from(&mut ini, "default").unwrap();
+        let dev = remove_from(&mut ini, "dev").unwrap();
+        let prod = remove_from(&mut ini, "prod").unwrap();
+        //Assert
+        assert!(!default);
+        assert!(dev && prod);
+        assert_eq!(
+            ini.to_string(),
+            "[default]\naws_access_key_id = AKIA\n\n[prod]\nregion = eu-west-1\n"
+        );
+    }
 }
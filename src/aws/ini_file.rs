@@ -0,0 +1,505 @@
+//! Format-preserving editor of the AWS config and credentials files.
+//!
+//! Only the edited lines are rewritten,
+//! so the comments, the blank lines and the order of the hand-curated files are kept as they are.
+use std::{fmt, fs, io, path::Path};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    /// `[name]`
+    Section { name: String, raw: String },
+    /// `key = value`
+    Property {
+        key: String,
+        value: String,
+        raw: String,
+    },
+    /// blank lines, comments, indented lines of nested values such as `s3 =`,
+    /// and whatever else, which are kept untouched
+    Other(String),
+}
+
+impl Line {
+    fn parse(raw: &str) -> Self {
+        let line = raw.trim_end_matches(['\r', '\n']);
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            return Line::Section {
+                name: trimmed[1..trimmed.len() - 1].trim().to_string(),
+                raw: raw.to_string(),
+            };
+        }
+        let is_other = trimmed.is_empty()
+            || trimmed.starts_with('#')
+            || trimmed.starts_with(';')
+            || line.starts_with(char::is_whitespace);
+        match line.split_once('=') {
+            Some((key, value)) if !is_other => Line::Property {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+                raw: raw.to_string(),
+            },
+            _ => Line::Other(raw.to_string()),
+        }
+    }
+
+    fn raw(&self) -> &str {
+        match self {
+            Line::Section { raw, .. } | Line::Property { raw, .. } | Line::Other(raw) => raw,
+        }
+    }
+
+    fn is_comment(&self) -> bool {
+        let trimmed = self.raw().trim();
+        trimmed.starts_with('#') || trimmed.starts_with(';')
+    }
+
+    fn is_blank(&self) -> bool {
+        self.raw().trim().is_empty()
+    }
+
+    /// Whether the line is a part of the values of the section
+    fn is_content(&self) -> bool {
+        match self {
+            Line::Property { .. } => true,
+            Line::Other(raw) => {
+                !self.is_blank() && !self.is_comment() && raw.starts_with(char::is_whitespace)
+            }
+            Line::Section { .. } => false,
+        }
+    }
+}
+
+/// An INI file of AWS, such as `~/.aws/config` and `~/.aws/credentials`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IniFile {
+    lines: Vec<Line>,
+    /// The line ending of the file, used for the new lines
+    newline: &'static str,
+}
+
+impl IniFile {
+    pub fn new() -> Self {
+        IniFile {
+            lines: Vec::new(),
+            newline: "\n",
+        }
+    }
+
+    /// Parse the content, which never fails since unknown lines are just kept
+    pub fn parse(content: &str) -> Self {
+        IniFile {
+            lines: content.split_inclusive('\n').map(Line::parse).collect(),
+            newline: if content.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            },
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Replace the file atomically, keeping its permissions (`0600` if it is new)
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        crate::atomic_file::write(path.as_ref(), self.to_string().as_bytes())
+    }
+
+    /// The names of the sections in the order of the file
+    pub fn section_names(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Section { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn section(&self, name: &str) -> Option<Section<'_>> {
+        let (header, end) = self.section_range(name)?;
+        Some(Section {
+            lines: &self.lines[header + 1..end],
+        })
+    }
+
+    /// A handle to edit the section, which is created on the first `set` if missing
+    pub fn with_section(&mut self, name: &str) -> SectionMut<'_> {
+        SectionMut {
+            file: self,
+            name: name.to_string(),
+        }
+    }
+
+    /// Set the value, rewriting only the line of the key
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let (header, end) = match self.section_range(section) {
+            Some(range) => range,
+            None => self.append_section(section),
+        };
+        for line in &mut self.lines[header + 1..end] {
+            if let Line::Property { key: k, raw, .. } = line {
+                if k == key {
+                    *line = Line::Property {
+                        key: key.to_string(),
+                        value: value.to_string(),
+                        raw: replace_value(raw, value),
+                    };
+                    return;
+                }
+            }
+        }
+        // after the last value, before the blank lines and the comments of the next section
+        let at = (header + 1..end)
+            .rev()
+            .find(|&i| self.lines[i].is_content())
+            .map(|i| i + 1)
+            .unwrap_or(header + 1);
+        self.terminate_line(at);
+        self.lines.insert(
+            at,
+            Line::Property {
+                key: key.to_string(),
+                value: value.to_string(),
+                raw: format!("{} = {}{}", key, value, self.newline),
+            },
+        );
+    }
+
+    /// Remove the key, returning its value
+    pub fn remove(&mut self, section: &str, key: &str) -> Option<String> {
+        let (header, end) = self.section_range(section)?;
+        let i = (header + 1..end)
+            .find(|&i| matches!(&self.lines[i], Line::Property { key: k, .. } if k == key))?;
+        match self.lines.remove(i) {
+            Line::Property { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Remove the section with its values and the comments just above it.
+    /// The comments just above the next section are kept since they describe it.
+    pub fn remove_section(&mut self, name: &str) -> bool {
+        let Some((header, end)) = self.section_range(name) else {
+            return false;
+        };
+        let mut cut = end;
+        while cut > header + 1
+            && (self.lines[cut - 1].is_blank() || self.lines[cut - 1].is_comment())
+        {
+            cut -= 1;
+        }
+        // keep the trailing block only if it has comments, starting from the first one
+        match (cut..end).find(|&i| self.lines[i].is_comment()) {
+            Some(comment) => cut = comment,
+            None => cut = end,
+        }
+        let mut start = header;
+        while start > 0 && self.lines[start - 1].is_comment() {
+            start -= 1;
+        }
+        if cut == self.lines.len() {
+            // no more sections, so the blank lines above are no longer needed
+            while start > 0 && self.lines[start - 1].is_blank() {
+                start -= 1;
+            }
+        }
+        self.lines.drain(start..cut);
+        true
+    }
+
+    /// Rename the section in place, keeping its values and comments
+    pub fn rename_section(&mut self, from: &str, to: &str) -> bool {
+        let Some((header, _)) = self.section_range(from) else {
+            return false;
+        };
+        let raw = self.lines[header].raw();
+        let ending = &raw[raw.trim_end_matches(['\r', '\n']).len()..];
+        self.lines[header] = Line::Section {
+            name: to.to_string(),
+            raw: format!("[{}]{}", to, ending),
+        };
+        true
+    }
+
+    /// The index of the header and the end of the first section of the name
+    fn section_range(&self, name: &str) -> Option<(usize, usize)> {
+        let header = self
+            .lines
+            .iter()
+            .position(|line| matches!(line, Line::Section { name: n, .. } if n == name))?;
+        let end = self.lines[header + 1..]
+            .iter()
+            .position(|line| matches!(line, Line::Section { .. }))
+            .map(|i| header + 1 + i)
+            .unwrap_or(self.lines.len());
+        Some((header, end))
+    }
+
+    /// Append an empty section separated by a blank line
+    fn append_section(&mut self, name: &str) -> (usize, usize) {
+        let len = self.lines.len();
+        self.terminate_line(len);
+        if self.lines.last().is_some_and(|line| !line.is_blank()) {
+            self.lines.push(Line::Other(self.newline.to_string()));
+        }
+        self.lines.push(Line::Section {
+            name: name.to_string(),
+            raw: format!("[{}]{}", name, self.newline),
+        });
+        (self.lines.len() - 1, self.lines.len())
+    }
+
+    /// Make sure the line before `at` ends with a newline, so that a line can be inserted at `at`
+    fn terminate_line(&mut self, at: usize) {
+        if at == 0 {
+            return;
+        }
+        let newline = self.newline;
+        let (Line::Section { raw, .. } | Line::Property { raw, .. } | Line::Other(raw)) =
+            &mut self.lines[at - 1];
+        if !raw.ends_with('\n') {
+            raw.push_str(newline);
+        }
+    }
+}
+
+impl Default for IniFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for IniFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.lines
+            .iter()
+            .try_for_each(|line| f.write_str(line.raw()))
+    }
+}
+
+/// Replace the value of a `key = value` line, keeping the key, the spaces and the line ending
+fn replace_value(raw: &str, value: &str) -> String {
+    let line = raw.trim_end_matches(['\r', '\n']);
+    let ending = &raw[line.len()..];
+    let (key, old_value) = line.split_once('=').unwrap_or((line, ""));
+    let spaces = &old_value[..old_value.len() - old_value.trim_start().len()];
+    format!("{}={}{}{}", key, spaces, value, ending)
+}
+
+/// The values of a section
+#[derive(Debug, Clone, Copy)]
+pub struct Section<'a> {
+    lines: &'a [Line],
+}
+
+impl<'a> Section<'a> {
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// The keys and the values in the order of the file
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Property { key, value, .. } => Some((key.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
+}
+
+/// A handle to edit a section
+pub struct SectionMut<'a> {
+    file: &'a mut IniFile,
+    name: String,
+}
+
+impl SectionMut<'_> {
+    pub fn set(&mut self, key: &str, value: impl AsRef<str>) -> &mut Self {
+        self.file.set(&self.name, key, value.as_ref());
+        self
+    }
+
+    pub fn remove(&mut self, key: &str) -> &mut Self {
+        self.file.remove(&self.name, key);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A hand-curated config with comments, blank lines, nested values and a file without the last newline
+    const GOLDEN_CONFIG: &str = r#"# my AWS config
+[default]
+region = ap-northeast-1
+output=json
+
+# production, managed by awsaml
+[profile prod]
+; the tenant of the corp
+entra_id_tenant = contoso.onmicrosoft.com
+app_id_uri   =   https://signin.aws.amazon.com/saml#1
+s3 =
+  max_concurrent_requests = 20
+
+# sandbox
+[profile sandbox]
+region = us-west-2"#;
+
+    #[test]
+    fn test_round_trip() {
+        //Arrange
+        let target = IniFile::parse(GOLDEN_CONFIG);
+        //Act
+        let result = target.to_string();
+        //Assert
+        assert_eq!(result, GOLDEN_CONFIG);
+    }
+
+    #[test]
+    fn test_round_trip_crlf() {
+        let content = GOLDEN_CONFIG.replace('\n', "\r\n");
+        assert_eq!(IniFile::parse(&content).to_string(), content);
+    }
+
+    #[test]
+    fn test_get() {
+        let target = IniFile::parse(GOLDEN_CONFIG);
+        let section = target.section("profile prod").unwrap();
+        assert_eq!(
+            section.get("app_id_uri"),
+            Some("https://signin.aws.amazon.com/saml#1")
+        );
+        assert_eq!(section.get("s3"), Some(""));
+        assert_eq!(section.get("max_concurrent_requests"), None);
+        assert_eq!(
+            target.section("default").unwrap().get("output"),
+            Some("json")
+        );
+        assert_eq!(
+            target.section_names().collect::<Vec<_>>(),
+            vec!["default", "profile prod", "profile sandbox"]
+        );
+    }
+
+    #[test]
+    fn test_set_existing_key() {
+        //Arrange
+        let mut target = IniFile::parse(GOLDEN_CONFIG);
+        //Act
+        target
+            .with_section("profile prod")
+            .set("app_id_uri", "https://signin.aws.amazon.com/saml#2");
+        target.with_section("default").set("output", "text");
+        //Assert
+        assert_eq!(
+            target.to_string(),
+            GOLDEN_CONFIG
+                .replace(
+                    "app_id_uri   =   https://signin.aws.amazon.com/saml#1",
+                    "app_id_uri   =   https://signin.aws.amazon.com/saml#2"
+                )
+                .replace("output=json", "output=text")
+        );
+    }
+
+    #[test]
+    fn test_set_new_key() {
+        //Arrange
+        let mut target = IniFile::parse(GOLDEN_CONFIG);
+        //Act
+        target
+            .with_section("profile prod")
+            .set("saml_role_arn", "arn:aws:iam::123456789012:role/Admin");
+        target.with_section("profile sandbox").set("output", "json");
+        //Assert
+        assert_eq!(
+            target.to_string(),
+            GOLDEN_CONFIG
+                .replace(
+                    "  max_concurrent_requests = 20\n",
+                    "  max_concurrent_requests = 20\nsaml_role_arn = arn:aws:iam::123456789012:role/Admin\n"
+                )
+                .replace("region = us-west-2", "region = us-west-2\noutput = json\n")
+        );
+    }
+
+    #[test]
+    fn test_set_new_section() {
+        //Arrange
+        let mut target = IniFile::parse("[default]\nregion = us-east-1\n");
+        //Act
+        target
+            .with_section("profile dev")
+            .set("region", "eu-west-1")
+            .set("output", "json");
+        //Assert
+        assert_eq!(
+            target.to_string(),
+            "[default]\nregion = us-east-1\n\n[profile dev]\nregion = eu-west-1\noutput = json\n"
+        );
+    }
+
+    #[test]
+    fn test_set_into_empty_file() {
+        let mut target = IniFile::new();
+        target.with_section("prod").set("aws_access_key_id", "AKIA");
+        assert_eq!(target.to_string(), "[prod]\naws_access_key_id = AKIA\n");
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut target = IniFile::parse(GOLDEN_CONFIG);
+        assert_eq!(target.remove("default", "output"), Some("json".to_string()));
+        assert_eq!(target.remove("default", "output"), None);
+        assert_eq!(
+            target.to_string(),
+            GOLDEN_CONFIG.replace("output=json\n", "")
+        );
+    }
+
+    #[test]
+    fn test_remove_section() {
+        //Arrange
+        let mut target = IniFile::parse(GOLDEN_CONFIG);
+        //Act
+        let removed = target.remove_section("profile prod");
+        //Assert
+        assert!(removed);
+        assert_eq!(
+            target.to_string(),
+            r#"# my AWS config
+[default]
+region = ap-northeast-1
+output=json
+
+# sandbox
+[profile sandbox]
+region = us-west-2"#
+        );
+    }
+
+    #[test]
+    fn test_remove_last_section() {
+        let mut target = IniFile::parse(GOLDEN_CONFIG);
+        target.remove_section("profile sandbox");
+        assert_eq!(
+            target.to_string(),
+            GOLDEN_CONFIG.replace("\n# sandbox\n[profile sandbox]\nregion = us-west-2", "")
+        );
+    }
+
+    #[test]
+    fn test_rename_section() {
+        let mut target = IniFile::parse("[prod]\r\nregion = us-east-1\r\n");
+        assert!(target.rename_section("prod", "profile prod"));
+        assert_eq!(
+            target.to_string(),
+            "[profile prod]\r\nregion = us-east-1\r\n"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! AWS partitions, which have their own sign-in and STS endpoints.
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Error, Result};
+
+/// A group of the AWS regions isolated from the others
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Partition {
+    /// The commercial regions
+    #[default]
+    Aws,
+    /// AWS GovCloud (US)
+    AwsUsGov,
+    /// The China regions
+    AwsCn,
+}
+
+impl Partition {
+    /// The partition of the region, e.g. `aws-us-gov` of `us-gov-west-1`
+    pub fn of_region(region: &str) -> Self {
+        if region.starts_with("us-gov-") {
+            Partition::AwsUsGov
+        } else if region.starts_with("cn-") {
+            Partition::AwsCn
+        } else {
+            Partition::Aws
+        }
+    }
+
+    /// The SAML sign-in endpoint, used as the ACS URL and captured as the callback
+    pub fn signin_url(&self) -> &'static str {
+        match self {
+            Partition::Aws => super::AWS_SAML_CALLBACK,
+            Partition::AwsUsGov => "https://signin.amazonaws-us-gov.com/saml",
+            Partition::AwsCn => "https://signin.amazonaws.cn/saml",
+        }
+    }
+
+    /// The region of the STS endpoint when none is configured
+    pub fn default_region(&self) -> &'static str {
+        match self {
+            Partition::Aws => "us-east-1",
+            Partition::AwsUsGov => "us-gov-west-1",
+            Partition::AwsCn => "cn-north-1",
+        }
+    }
+
+    /// Whether the partition has the FIPS endpoints
+    pub fn has_fips(&self) -> bool {
+        *self != Partition::AwsCn
+    }
+}
+
+impl FromStr for Partition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "aws" => Ok(Partition::Aws),
+            "aws-us-gov" => Ok(Partition::AwsUsGov),
+            "aws-cn" => Ok(Partition::AwsCn),
+            _ => Err(Error::Config(format!(
+                "Unknown partition: {}, expected aws, aws-us-gov or aws-cn",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Partition::Aws => write!(f, "aws"),
+            Partition::AwsUsGov => write!(f, "aws-us-gov"),
+            Partition::AwsCn => write!(f, "aws-cn"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_of_region() {
+        assert_eq!(Partition::of_region("eu-west-1"), Partition::Aws);
+        assert_eq!(Partition::of_region("us-gov-east-1"), Partition::AwsUsGov);
+        assert_eq!(Partition::of_region("cn-northwest-1"), Partition::AwsCn);
+    }
+
+    #[test]
+    fn test_from_str() {
+        //Arrange
+        let values = ["aws", "aws-us-gov", "aws-cn"];
+        //Act
+        let partitions = values.map(|v| v.parse::<Partition>().unwrap());
+        //Assert
+        assert_eq!(partitions.map(|p| p.to_string()), values);
+        assert!(matches!(
+            "aws-iso".parse::<Partition>(),
+            Err(Error::Config(_))
+        ));
+    }
+}
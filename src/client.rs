@@ -0,0 +1,377 @@
+//! High-level entry point to run the whole login flow from other tools.
+use crate::aws::{assume_role_with_saml, CredentialSink, Credentials, StsSettings};
+use crate::saml::{
+    ChromeLaunchOptions, ChromeSamlAgent, ManualSamlAgent, SamlAgent, SamlAuthRequest,
+    SamlIdProvider, SamlLogoutRequest, SamlResponse, SamlResponseSource, SamlRole,
+    DEFAULT_LOGIN_TIMEOUT,
+};
+use crate::{Error, Result};
+use std::path::PathBuf;
+use std::sync::{atomic::AtomicBool, Arc};
+use std::time::Duration;
+use url::Url;
+
+/// Chooses the role to assume when the assertion grants several roles
+pub type RoleChooser = dyn Fn(&[SamlRole]) -> Result<SamlRole> + Send + Sync;
+
+/// How to run the browser login with Chrome
+#[derive(Debug, Clone)]
+pub struct ChromeSettings {
+    pub user_data_dir: PathBuf,
+    pub launch_options: ChromeLaunchOptions,
+    pub login_timeout: Duration,
+    /// Try a headless login first for this long, if any
+    pub silent_login_timeout: Option<Duration>,
+    /// Attach to a running Chrome instead of launching a new one
+    pub remote_debugging_url: Option<String>,
+    /// Save the diagnostics of a failed login into this directory
+    pub debug_bundle_dir: Option<PathBuf>,
+    /// Cancel the login on Ctrl-C while waiting for the browser, instead of being terminated
+    pub cancel_on_ctrl_c: bool,
+}
+
+impl ChromeSettings {
+    pub fn new(user_data_dir: PathBuf) -> Self {
+        ChromeSettings {
+            user_data_dir,
+            launch_options: ChromeLaunchOptions::default(),
+            login_timeout: DEFAULT_LOGIN_TIMEOUT,
+            silent_login_timeout: None,
+            remote_debugging_url: None,
+            debug_bundle_dir: None,
+            cancel_on_ctrl_c: false,
+        }
+    }
+}
+
+/// How the SAML response is acquired from the IdP
+#[derive(Debug)]
+pub enum AgentStrategy {
+    /// Sign in with a Chrome driven by awsaml
+    Chrome(ChromeSettings),
+    /// Sign in with any browser, and hand over the SAMLResponse
+    Manual(SamlResponseSource),
+    /// Any other agent, which already knows the IdP
+    Custom(Box<dyn SamlAgent>),
+}
+
+/// Builds a [`Client`].
+/// The IdP, the app ID URI and the agent strategy are required.
+pub struct LoginBuilder {
+    idp: Option<Box<dyn SamlIdProvider>>,
+    app_id_uri: Option<Url>,
+    callback_url: Option<Url>,
+    agent: Option<AgentStrategy>,
+    sts: StsSettings,
+    session_duration: Option<Duration>,
+    role_name: Option<String>,
+    role_chooser: Option<Box<RoleChooser>>,
+    sink: Option<Box<dyn CredentialSink>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Default for LoginBuilder {
+    fn default() -> Self {
+        LoginBuilder {
+            idp: None,
+            app_id_uri: None,
+            callback_url: None,
+            agent: None,
+            sts: StsSettings::default(),
+            session_duration: None,
+            role_name: None,
+            role_chooser: None,
+            sink: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl LoginBuilder {
+    /// The IdP to sign in
+    pub fn idp(mut self, idp: Box<dyn SamlIdProvider>) -> Self {
+        self.idp = Some(idp);
+        self
+    }
+
+    /// The identifier of the SP registered in the IdP
+    pub fn app_id_uri(mut self, app_id_uri: Url) -> Self {
+        self.app_id_uri = Some(app_id_uri);
+        self
+    }
+
+    /// The URL where the IdP posts the SAML response,
+    /// the AWS sign-in endpoint of the STS partition by default
+    pub fn callback_url(mut self, callback_url: Url) -> Self {
+        self.callback_url = Some(callback_url);
+        self
+    }
+
+    pub fn agent(mut self, agent: AgentStrategy) -> Self {
+        self.agent = Some(agent);
+        self
+    }
+
+    pub fn sts(mut self, sts: StsSettings) -> Self {
+        self.sts = sts;
+        self
+    }
+
+    /// The duration of the role session to request,
+    /// capped by the `SessionDuration` of the assertion and the maximum of the role.
+    /// The role's default (1 hour) if not set.
+    pub fn session_duration(mut self, duration: Duration) -> Self {
+        self.session_duration = Some(duration);
+        self
+    }
+
+    /// The name or the ARN of the role to assume
+    pub fn role(mut self, role_name: impl Into<String>) -> Self {
+        self.role_name = Some(role_name.into());
+        self
+    }
+
+    /// Choose the role when the assertion grants several roles and none is named.
+    /// Without it, the login fails in that case.
+    pub fn choose_role_with(
+        mut self,
+        chooser: impl Fn(&[SamlRole]) -> Result<SamlRole> + Send + Sync + 'static,
+    ) -> Self {
+        self.role_chooser = Some(Box::new(chooser));
+        self
+    }
+
+    /// Where to store the credentials, if anywhere
+    pub fn credential_sink(mut self, sink: Box<dyn CredentialSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// A flag to cancel the browser login from another thread (e.g. a Ctrl-C handler)
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let app_id_uri = self
+            .app_id_uri
+            .ok_or(Error::Config("The app ID URI is required".to_string()))?;
+        let agent = self
+            .agent
+            .ok_or(Error::Config("The agent strategy is required".to_string()))?;
+        self.sts.validate()?;
+        let callback_url = self
+            .callback_url
+            .unwrap_or_else(|| Url::parse(self.sts.partition.signin_url()).unwrap());
+        let logout_url = self.idp.as_ref().and_then(|idp| idp.logout_url());
+        let agent: Box<dyn SamlAgent> = match agent {
+            AgentStrategy::Custom(agent) => agent,
+            AgentStrategy::Manual(source) => {
+                let idp = self
+                    .idp
+                    .ok_or(Error::Config("The IdP is required".to_string()))?;
+                Box::new(ManualSamlAgent::new(idp, source))
+            }
+            AgentStrategy::Chrome(settings) => {
+                let idp = self
+                    .idp
+                    .ok_or(Error::Config("The IdP is required".to_string()))?;
+                let mut agent =
+                    ChromeSamlAgent::new(idp, callback_url.clone(), settings.user_data_dir)
+                        .with_launch_options(settings.launch_options)
+                        .with_timeout(settings.login_timeout)
+                        .with_cancel_handle(self.cancelled);
+                if let Some(silent_login_timeout) = settings.silent_login_timeout {
+                    agent = agent.with_silent_login(silent_login_timeout);
+                }
+                if let Some(url) = settings.remote_debugging_url {
+                    agent = agent.with_remote_debugging(url);
+                }
+                if let Some(dir) = settings.debug_bundle_dir {
+                    agent = agent.with_debug_bundle(dir);
+                }
+                if settings.cancel_on_ctrl_c {
+                    agent = agent.with_cancel_on_ctrl_c();
+                }
+                Box::new(agent)
+            }
+        };
+        Ok(Client {
+            agent,
+            app_id_uri,
+            callback_url,
+            logout_url,
+            sts: self.sts,
+            session_duration: self.session_duration,
+            role_name: self.role_name,
+            role_chooser: self.role_chooser,
+            sink: self.sink,
+            saml_response: None,
+        })
+    }
+}
+
+/// Signs in the IdP, and exchanges the SAML assertion for AWS credentials
+pub struct Client {
+    agent: Box<dyn SamlAgent>,
+    app_id_uri: Url,
+    callback_url: Url,
+    logout_url: Option<Url>,
+    sts: StsSettings,
+    session_duration: Option<Duration>,
+    role_name: Option<String>,
+    role_chooser: Option<Box<RoleChooser>>,
+    sink: Option<Box<dyn CredentialSink>>,
+    saml_response: Option<SamlResponse>,
+}
+
+impl Client {
+    pub fn builder() -> LoginBuilder {
+        LoginBuilder::default()
+    }
+
+    /// Run the whole flow, and return the credentials after storing them into the sink
+    pub async fn login(&mut self) -> Result<Credentials> {
+        let saml_req = SamlAuthRequest::new(self.app_id_uri.clone(), self.callback_url.clone());
+        log::debug!("{:?}", &saml_req);
+        log::debug!("{:?}", &self.agent);
+        let saml_res = self.agent.saml_request_to_idp(saml_req).await?;
+        log::debug!("{:?}", &saml_res);
+
+        let role = self.select_role(saml_res.roles()?)?;
+        let credentials =
+            assume_role_with_saml(&saml_res, &role, self.session_duration, &self.sts).await?;
+        if let Some(sink) = &self.sink {
+            sink.store(&role, &credentials)?;
+        }
+        self.saml_response = Some(saml_res);
+        Ok(credentials)
+    }
+
+    /// The assertion of the last successful login
+    pub fn saml_response(&self) -> Option<&SamlResponse> {
+        self.saml_response.as_ref()
+    }
+
+    /// End the IdP session in which the assertion was issued, by the SAML single logout.
+    /// The credentials already issued stay valid until they expire.
+    pub async fn logout(&mut self, saml_response: &SamlResponse) -> Result<()> {
+        let logout_url = self.logout_url.clone().ok_or(Error::Config(
+            "The IdP does not support the single logout".to_string(),
+        ))?;
+        let logout_req =
+            SamlLogoutRequest::new(self.app_id_uri.clone(), logout_url, saml_response)?;
+        log::debug!("{:?}", &logout_req);
+        let logout_res = self.agent.logout_request_to_idp(logout_req.clone()).await?;
+        log::debug!("{:?}", &logout_res);
+        logout_res.verify(&logout_req)
+    }
+
+    /// Choose the role to assume among the roles granted by the assertion
+    fn select_role(&self, roles: Vec<SamlRole>) -> Result<SamlRole> {
+        if let Some(role_name) = &self.role_name {
+            return roles
+                .into_iter()
+                .find(|role| role.name() == role_name || &role.role_arn == role_name)
+                .ok_or(Error::Config(format!("Role[{}] is not granted", role_name)));
+        }
+        if roles.len() == 1 {
+            return Ok(roles.into_iter().next().unwrap());
+        }
+        match &self.role_chooser {
+            Some(chooser) => chooser(&roles),
+            None => Err(Error::Config(format!(
+                "Several roles are granted, choose one of {}",
+                roles
+                    .iter()
+                    .map(|role| role.role_arn.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::Partition;
+    use async_trait::async_trait;
+
+    #[derive(Debug)]
+    struct NoAgent;
+
+    #[async_trait]
+    impl SamlAgent for NoAgent {
+        async fn saml_request_to_idp(&mut self, _: SamlAuthRequest) -> Result<SamlResponse> {
+            Err(Error::Config("not used".to_string()))
+        }
+    }
+
+    fn roles() -> Vec<SamlRole> {
+        ["Admin", "ReadOnly"]
+            .iter()
+            .map(|name| SamlRole {
+                role_arn: format!("arn:aws:iam::123456789012:role/{}", name),
+                principal_arn: "arn:aws:iam::123456789012:saml-provider/EntraID".to_string(),
+            })
+            .collect()
+    }
+
+    fn builder() -> LoginBuilder {
+        Client::builder()
+            .app_id_uri(Url::parse("https://signin.aws.amazon.com/saml#1").unwrap())
+            .agent(AgentStrategy::Custom(Box::new(NoAgent)))
+    }
+
+    #[test]
+    fn test_select_role_by_name() {
+        //Arrange
+        let client = builder().role("ReadOnly").build().unwrap();
+        //Act
+        let result = client.select_role(roles()).unwrap();
+        //Assert
+        assert_eq!(result.role_arn, "arn:aws:iam::123456789012:role/ReadOnly");
+    }
+
+    #[test]
+    fn test_select_role_without_chooser() {
+        let client = builder().build().unwrap();
+        assert!(matches!(client.select_role(roles()), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_build_callback_of_partition() {
+        //Arrange
+        let sts = StsSettings {
+            region: Some("us-gov-west-1".to_string()),
+            partition: Partition::AwsUsGov,
+            ..Default::default()
+        };
+        //Act
+        let client = builder().sts(sts).build().unwrap();
+        //Assert
+        assert_eq!(
+            client.callback_url.as_str(),
+            "https://signin.amazonaws-us-gov.com/saml"
+        );
+    }
+
+    #[test]
+    fn test_build_region_out_of_partition() {
+        let sts = StsSettings {
+            region: Some("eu-west-1".to_string()),
+            partition: Partition::AwsCn,
+            ..Default::default()
+        };
+        assert!(matches!(builder().sts(sts).build(), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_build_without_idp() {
+        let result = builder()
+            .agent(AgentStrategy::Manual(SamlResponseSource::Stdin))
+            .build();
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+}
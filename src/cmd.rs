@@ -1,18 +1,33 @@
 pub mod config;
 pub mod prompt;
+pub mod segment;
+pub mod status;
 
 use anyhow::Result;
-use awsaml::aws::{assume_role_with_saml, AWS_SAML_CALLBACK};
+use awsaml::aws::{self, Credentials, CredentialsFile, SectionName, StsSettings};
+use awsaml::lock::FileLock;
 use awsaml::saml::entra_id::EntraIdSamlIdProvider;
-use awsaml::saml::{ChromeSamlAgent, SamlAuthRequest};
-use config::Config;
-use url::Url;
+use awsaml::saml::{
+    self, cache, ChromeLaunchOptions, SamlIdProvider, SamlResponseSource, SamlRole, SessionTag,
+};
+use awsaml::{AgentStrategy, ChromeSettings, Client};
+use config::{ChainedProfile, Config, ConfigureArgs};
+use prompt::{Prompt, Required};
+use segment::Shell;
+use status::SessionStatus;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// How long the credentials acquired by a concurrent login have to be valid to be reused
+const MIN_REUSED_VALIDITY: chrono::Duration = chrono::Duration::minutes(5);
 
 /// Configure the AWSaml CLI.
 /// This will prompt the user for the necessary configuration values
 /// and save them to '~/.aws/config' file.
-pub fn configure(profile: &String) -> Result<()> {
-    let new_config = Config::configure()?;
+pub fn configure(profile: &str, args: ConfigureArgs) -> Result<()> {
+    let new_config = Config::configure(args.with_env().with_session_defaults()?)?;
     new_config.save(profile)?;
     Ok(())
 }
@@ -24,26 +39,615 @@ pub fn configure(profile: &String) -> Result<()> {
 /// and call `assumeRoleWithSAML` to acquire AWS credentials.
 ///
 /// Aquired credentials will be saved to `~/.aws/credentials` file.
-pub fn login(profile: &String, _role_name: Option<String>) -> Result<()> {
+///
+/// With `manual`, the login URL is printed instead of opening a browser,
+/// and the SAMLResponse is read from the prompt,
+/// or from `saml_response_file` ("-" means stdin) if given.
+///
+/// With `debug_bundle`, the diagnostics of a failed browser login are saved into the directory.
+///
+/// The role is chosen by `role_name` or the `saml_role_arn` of the profile,
+/// or asked if the assertion grants several roles.
+///
+/// A profile with `source_profile` assumes its `role_arn` with the credentials of the source profile,
+/// which logs in first unless its credentials are still valid.
+/// The AWS CLI does not use the credentials saved into such a profile, see [`chained_login`].
+pub fn login(
+    profile: &String,
+    role_name: Option<String>,
+    manual: bool,
+    saml_response_file: Option<PathBuf>,
+    debug_bundle: Option<PathBuf>,
+) -> Result<()> {
+    let Some(chained) = ChainedProfile::load(profile)? else {
+        saml_login(profile, role_name, manual, saml_response_file, debug_bundle)?;
+        return Ok(());
+    };
+    log::debug!("{:?}", &chained);
+
+    chained_login(
+        &aws::credentials::file_path(),
+        profile,
+        &chained,
+        || {
+            saml_login(
+                &chained.source_profile,
+                role_name,
+                manual,
+                saml_response_file,
+                debug_bundle,
+            )
+        },
+        |source| {
+            Ok(block_on(aws::assume_role(
+                source,
+                &chained.role,
+                &chained.sts,
+            ))?)
+        },
+    )?;
+    Ok(())
+}
+
+/// Assume the role of the chained profile with the credentials of its source profile,
+/// calling `source_login` only if the source has no valid credentials,
+/// and save the credentials into the chained profile of the credentials file.
+///
+/// The AWS CLI and the SDKs assume `role_arn` of the profile by themselves,
+/// since their AssumeRole provider comes before the credentials file;
+/// the saved credentials serve the tools which read only the credentials file.
+fn chained_login(
+    credentials_file: &str,
+    profile: &str,
+    chained: &ChainedProfile,
+    source_login: impl FnOnce() -> Result<Credentials>,
+    assume_role: impl FnOnce(&Credentials) -> Result<Credentials>,
+) -> Result<Credentials> {
+    let (_lock, reused) = lock_login(credentials_file, profile)?;
+    if let Some(credentials) = reused {
+        return Ok(credentials);
+    }
+    let source = match Credentials::load_from(credentials_file, &chained.source_profile)?
+        .filter(|c| c.is_valid_for(MIN_REUSED_VALIDITY))
+    {
+        Some(credentials) => {
+            eprintln!(
+                "Using the credentials of [{}], valid until {}",
+                chained.source_profile, credentials.expiration
+            );
+            credentials
+        }
+        None => source_login()?,
+    };
+
+    let credentials = assume_role(&source)?;
+    credentials.save_to(credentials_file, profile)?;
+    eprintln!(
+        "Saved the credentials to [{}], valid until {}",
+        profile, credentials.expiration
+    );
+    Ok(credentials)
+}
+
+/// Login to the IdP and assume the role of the SAML assertion
+fn saml_login(
+    profile: &String,
+    role_name: Option<String>,
+    manual: bool,
+    saml_response_file: Option<PathBuf>,
+    debug_bundle: Option<PathBuf>,
+) -> Result<Credentials> {
     let config = Config::load(profile)?;
     log::debug!("{:?}", &config);
 
-    let saml_req = SamlAuthRequest::new(
-        Url::parse(&config.app_id_uri)?,
-        Url::parse(AWS_SAML_CALLBACK)?,
+    let (_lock, reused) = lock_login(&aws::credentials::file_path(), profile)?;
+    if let Some(credentials) = reused {
+        return Ok(credentials);
+    }
+
+    let agent = match saml_response_file {
+        Some(path) if path.as_os_str() == "-" => AgentStrategy::Manual(SamlResponseSource::Stdin),
+        Some(path) => AgentStrategy::Manual(SamlResponseSource::File(path)),
+        None if manual => AgentStrategy::Manual(SamlResponseSource::Prompt),
+        None => AgentStrategy::Chrome(chrome_settings(&config, debug_bundle)),
+    };
+
+    let mut builder = Client::builder()
+        .idp(idp_of(&config)?)
+        .app_id_uri(config.app_id_url()?)
+        .agent(agent)
+        .sts(config.sts)
+        .choose_role_with(choose_role)
+        .credential_sink(Box::new(CredentialsFile::new(profile.to_string())));
+    // older configurations may lack it, then the role's default applies
+    let requested = u64::try_from(config.session_duration_hours)
+        .ok()
+        .filter(|hours| *hours > 0)
+        .map(|hours| Duration::from_secs(hours * 3600));
+    if let Some(duration) = requested {
+        builder = builder.session_duration(duration);
+    }
+    if let Some(role_name) = role_name.or(config.saml_role_arn) {
+        builder = builder.role(role_name);
+    }
+
+    let mut client = builder.build()?;
+    let credentials = block_on(client.login())?;
+    if let Some(saml_response) = client.saml_response() {
+        cache::save(profile, saml_response)?;
+    }
+    let granted = (credentials.expiration - chrono::Utc::now()).num_minutes();
+    eprintln!(
+        "Saved the credentials to [{}], valid until {} ({} minutes)",
+        profile, credentials.expiration, granted
     );
-    log::debug!("{:?}", &saml_req);
+    if let Some(requested) = requested {
+        // a minute of slack for the time spent since the STS call
+        if granted < (requested.as_secs() / 60) as i64 - 1 {
+            eprintln!(
+                "The session was granted for {} minutes instead of the requested {} hours, limited by the IdP or the role",
+                granted,
+                requested.as_secs() / 3600
+            );
+        }
+    }
+    Ok(credentials)
+}
+
+/// How to drive Chrome for the profile
+fn chrome_settings(config: &Config, debug_bundle: Option<PathBuf>) -> ChromeSettings {
+    ChromeSettings {
+        user_data_dir: config.chrome_user_data_dir.clone(),
+        launch_options: ChromeLaunchOptions {
+            path: config.chrome_path.clone(),
+            args: config.chrome_args.clone(),
+            sandbox: config.chrome_sandbox,
+            idle_timeout: Duration::from_secs(config.chrome_idle_timeout_seconds),
+        },
+        login_timeout: Duration::from_secs(config.login_timeout_seconds),
+        silent_login_timeout: Some(Duration::from_secs(config.silent_login_timeout_seconds))
+            .filter(|timeout| !timeout.is_zero()),
+        remote_debugging_url: config.chrome_remote_debugging_url.clone(),
+        debug_bundle_dir: debug_bundle,
+        cancel_on_ctrl_c: true,
+    }
+}
+
+/// The IdP configured for the profile
+fn idp_of(config: &Config) -> Result<Box<dyn SamlIdProvider>> {
+    match config.idp.as_str() {
+        config::DEFAULT_IDP => Ok(Box::new(EntraIdSamlIdProvider::new(
+            config.entra_id_tenant.to_string(),
+        ))),
+        idp => Err(awsaml::Error::Config(format!("Unsupported identity provider: {}", idp)).into()),
+    }
+}
+
+/// Run an async call of the library to completion.
+/// The CLI itself is blocking, and the runtime only drives the calls into the library.
+fn block_on<F: Future>(future: F) -> F::Output {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to start the async runtime")
+        })
+        .block_on(future)
+}
 
-    let entra_id = EntraIdSamlIdProvider::new(config.entra_id_tenant.to_string());
-    let mut agent = ChromeSamlAgent::new(
-        Box::new(entra_id),
-        Url::parse(AWS_SAML_CALLBACK)?,
-        config.chrome_user_data_dir,
+/// Take the login lock of the profile of the credentials file.
+/// Concurrent logins of the same profile wait for the first one instead of opening more windows,
+/// and reuse its credentials if they are valid.
+fn lock_login(credentials_file: &str, profile: &str) -> Result<(FileLock, Option<Credentials>)> {
+    let lock_path = aws::state_dir_beside(credentials_file)
+        .join(format!("login-{}", aws::file_name_of(profile)));
+    if let Some(lock) = FileLock::try_acquire(&lock_path)? {
+        return Ok((lock, None));
+    }
+    eprintln!(
+        "Waiting for another awsaml login of [{}] to complete...",
+        profile
     );
-    log::debug!("{:?}", &agent);
+    wait_login(&lock_path, credentials_file, profile)
+}
 
-    let saml_res = agent.saml_request_to_idp(saml_req)?;
-    println!("{:?}", &saml_res);
-    assume_role_with_saml(saml_res);
+/// Wait for the login lock of the profile held by another login,
+/// then take over the credentials it saved if they are valid.
+fn wait_login(
+    lock_path: &Path,
+    credentials_file: &str,
+    profile: &str,
+) -> Result<(FileLock, Option<Credentials>)> {
+    let lock = FileLock::acquire(lock_path)?;
+    let reused = Credentials::load_from(credentials_file, profile)?
+        .filter(|c| c.is_valid_for(MIN_REUSED_VALIDITY));
+    if let Some(credentials) = &reused {
+        eprintln!(
+            "Reusing the credentials of [{}] from the other login, valid until {}",
+            profile, credentials.expiration
+        );
+    }
+    Ok((lock, reused))
+}
+
+/// Show the sessions saved by awsaml, of the profile or of all the profiles with `all`.
+///
+/// With `verify`, the credentials are checked by STS `GetCallerIdentity`,
+/// calling `endpoint_url` instead of the endpoint of the profile if given.
+/// With `json`, an object (or an array with `all`) is printed for scripts.
+///
+/// For a single profile, the session tags of its last assertion are shown as well,
+/// with the tags added by the profile if it is chained.
+pub fn whoami(
+    profile: &str,
+    all: bool,
+    verify: bool,
+    endpoint_url: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let sessions = if all {
+        Credentials::load_all()?
+    } else {
+        let credentials = Credentials::load(profile)?.ok_or_else(|| {
+            awsaml::Error::Config(format!("Profile[{}] has not logged in", profile))
+        })?;
+        vec![(profile.to_string(), credentials)]
+    };
+
+    let mut statuses = Vec::new();
+    for (profile, credentials) in &sessions {
+        let mut status = SessionStatus::new(profile, credentials);
+        if verify {
+            let mut sts = sts_of(profile);
+            if endpoint_url.is_some() {
+                sts.endpoint_url = endpoint_url.clone();
+            }
+            status.verified_by(block_on(aws::get_caller_identity(credentials, &sts)));
+        }
+        statuses.push(status);
+    }
+
+    match (all, json) {
+        (true, true) => println!("{}", serde_json::to_string_pretty(&statuses)?),
+        (false, true) => println!("{}", serde_json::to_string_pretty(&statuses[0])?),
+        (true, false) => println!("{}", status::to_table(&statuses)),
+        (false, false) => {
+            for line in statuses[0].to_lines() {
+                println!("{}", line);
+            }
+            print_session_tags(profile)?;
+        }
+    }
     Ok(())
 }
+
+/// Remove the local state of the profile, or of all the profiles with `all`,
+/// so that a shared machine can be handed over:
+/// the credentials saved by awsaml and the assertions of the last logins.
+///
+/// With `wipe_cookies`, the cookies kept in the `chrome_user_data_dir` are removed as well,
+/// so that the next login asks the credentials of the IdP again.
+/// With `idp`, the IdP session itself is ended by the single logout before anything is removed.
+pub fn logout(profile: &str, all: bool, wipe_cookies: bool, idp: bool) -> Result<()> {
+    // the directories are read before the profiles are touched
+    let user_data_dirs = if wipe_cookies {
+        chrome_user_data_dirs(profile, all)?
+    } else {
+        Vec::new()
+    };
+    // the IdP session is ended while its assertion and cookies are still there.
+    // the local files are removed even if it fails.
+    let idp_result = if idp { idp_logout(profile) } else { Ok(()) };
+
+    let removed = if all {
+        Credentials::remove_all()?
+    } else if Credentials::remove(profile)? {
+        vec![profile.to_string()]
+    } else {
+        Vec::new()
+    };
+    for profile in &removed {
+        println!("Removed the credentials of [{}]", profile);
+    }
+    if all {
+        println!("Removed {} assertions", cache::remove_all()?);
+    } else if cache::remove(profile)? {
+        println!("Removed the assertion of [{}]", profile);
+    }
+
+    for dir in user_data_dirs {
+        let files = saml::wipe_cookies(&dir)?;
+        println!("Removed {} cookie files in {}", files.len(), dir.display());
+    }
+    idp_result
+}
+
+/// End the IdP session of the last assertion of the profile (or of its source profile)
+/// by the SAML single logout
+fn idp_logout(profile: &str) -> Result<()> {
+    let saml_profile = match ChainedProfile::load(profile)? {
+        Some(chained) => chained.source_profile,
+        None => profile.to_string(),
+    };
+    let config = Config::load(&saml_profile)?;
+    let saml_response = cache::load(&saml_profile)?.ok_or(awsaml::Error::Config(format!(
+        "No assertion of [{}] is saved, log in again to end its IdP session",
+        saml_profile
+    )))?;
+
+    let mut client = Client::builder()
+        .idp(idp_of(&config)?)
+        .app_id_uri(config.app_id_url()?)
+        .agent(AgentStrategy::Chrome(chrome_settings(&config, None)))
+        .sts(config.sts)
+        .build()?;
+    block_on(client.logout(&saml_response)).map_err(|e| match e {
+        awsaml::Error::Timeout(_) => anyhow::Error::new(e).context(
+            "No LogoutResponse came back. Entra ID sends it only to the Logout URL \
+             of the enterprise application, so set one in its SAML settings; \
+             awsaml catches the redirect, so the URL does not have to serve anything",
+        ),
+        e => e.into(),
+    })?;
+    println!("Ended the IdP session of [{}]", saml_profile);
+    Ok(())
+}
+
+/// The Chrome user data dirs of the profile (or of its source profile), or of all the profiles
+fn chrome_user_data_dirs(profile: &str, all: bool) -> Result<Vec<PathBuf>> {
+    let profiles = if all {
+        let awsconfig = aws::Config::load()?;
+        awsconfig
+            .ini
+            .section_names()
+            .filter_map(|name| match SectionName::parse(name) {
+                Some(SectionName::Default) => Some("default".to_string()),
+                Some(SectionName::Profile(name)) => Some(name),
+                // a legacy section without the `profile` prefix
+                None => Some(name.to_string()),
+                Some(_) => None,
+            })
+            .collect::<Vec<_>>()
+    } else {
+        match ChainedProfile::load(profile)? {
+            Some(chained) => vec![chained.source_profile],
+            None => vec![profile.to_string()],
+        }
+    };
+
+    let mut dirs = Vec::new();
+    for profile in profiles {
+        // the profiles not configured by awsaml have no directory
+        let Ok(config) = Config::load(&profile) else {
+            continue;
+        };
+        let dir = config.chrome_user_data_dir;
+        if !dir.as_os_str().is_empty() && !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    Ok(dirs)
+}
+
+/// Print the session of the profile for the shell prompt,
+/// or the snippet to put in the configuration of `init` shell.
+///
+/// Only the credentials file is read without a lock, so that the prompt is not slowed down,
+/// and nothing is printed if it fails.
+pub fn prompt_segment(
+    profile: &str,
+    shell: Shell,
+    warn_minutes: i64,
+    init: Option<Shell>,
+) -> Result<()> {
+    if let Some(shell) = init {
+        print!("{}", segment::snippet(shell));
+        return Ok(());
+    }
+    let credentials = Credentials::load(profile).unwrap_or_else(|e| {
+        log::debug!("{:?}", e);
+        None
+    });
+    print!(
+        "{}",
+        segment::segment(
+            profile,
+            credentials.as_ref(),
+            chrono::Duration::minutes(warn_minutes),
+            shell
+        )
+    );
+    Ok(())
+}
+
+/// How to call STS for the profile.
+/// The profiles not configured by awsaml call the default endpoint.
+fn sts_of(profile: &str) -> StsSettings {
+    match ChainedProfile::load(profile) {
+        Ok(Some(chained)) => chained.sts,
+        _ => Config::load(&profile.to_string())
+            .map(|config| config.sts)
+            .unwrap_or_default(),
+    }
+}
+
+/// Show the session tags from the last assertion of the profile,
+/// and the ones added by the profile if it is chained.
+fn print_session_tags(profile: &str) -> Result<()> {
+    let chained = ChainedProfile::load(profile)?;
+    let saml_profile = chained
+        .as_ref()
+        .map_or(profile, |chained| chained.source_profile.as_str());
+    if let Some(saml_response) = cache::load(saml_profile)? {
+        println!(
+            "Role session name: {}",
+            saml_response.role_session_name()?.unwrap_or_default()
+        );
+        println!(
+            "Session tags: {}",
+            format_tags(&saml_response.session_tags()?)
+        );
+        println!(
+            "Transitive tag keys: {}",
+            saml_response.transitive_tag_keys()?.join(", ")
+        );
+    }
+    if let Some(chained) = chained {
+        println!(
+            "Chained from [{}] into {}",
+            chained.source_profile, chained.role.role_arn
+        );
+        println!(
+            "Chained session tags: {}",
+            format_tags(&chained.role.session_tags)
+        );
+        println!(
+            "Chained transitive tag keys: {}",
+            chained.role.transitive_tag_keys.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn format_tags(tags: &[SessionTag]) -> String {
+    tags.iter()
+        .map(|tag| format!("{}={}", tag.key, tag.value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Ask the user which role to assume
+fn choose_role(roles: &[SamlRole]) -> awsaml::Result<SamlRole> {
+    for (i, role) in roles.iter().enumerate() {
+        println!("{}: {}", i + 1, role.role_arn);
+    }
+    let index: usize = Prompt {
+        msg: "Choose the role".to_string(),
+        required: Required::Yes(None),
+    }
+    .get_value()
+    .map_err(|e| awsaml::Error::Config(e.to_string()))?;
+    roles
+        .get(index.wrapping_sub(1))
+        .cloned()
+        .ok_or(awsaml::Error::Config(format!("No role numbered {}", index)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use awsaml::aws::ChainedRole;
+    use std::cell::Cell;
+
+    /// A credentials file in a new temporary directory
+    fn temp_credentials_file() -> String {
+        let dir = std::env::temp_dir().join(format!("awsaml-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("credentials").to_str().unwrap().to_string()
+    }
+
+    fn credentials(access_key_id: &str) -> Credentials {
+        Credentials {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: "secret".to_string(),
+            session_token: "token".to_string(),
+            expiration: chrono::Utc::now() + chrono::Duration::hours(1),
+            assumed_role_arn: None,
+            source_identity: None,
+        }
+    }
+
+    fn chained(source_profile: &str) -> ChainedProfile {
+        ChainedProfile {
+            source_profile: source_profile.to_string(),
+            role: ChainedRole {
+                role_arn: "arn:aws:iam::123456789012:role/Deploy".to_string(),
+                role_session_name: "awsaml-test".to_string(),
+                external_id: None,
+                duration_seconds: None,
+                session_tags: Vec::new(),
+                transitive_tag_keys: Vec::new(),
+            },
+            sts: StsSettings::default(),
+        }
+    }
+
+    #[test]
+    fn test_lock_login_waits_and_reuses() {
+        //Arrange
+        let file = temp_credentials_file();
+        let (lock, first) = lock_login(&file, "single-flight").unwrap();
+        let lock_path = aws::state_dir_beside(&file).join("login-single-flight");
+        //Act
+        // the second login has found the lock taken, whenever its thread runs
+        let waiter = std::thread::spawn({
+            let file = file.clone();
+            move || wait_login(&lock_path, &file, "single-flight").map(|(_, reused)| reused)
+        });
+        // it cannot go on until the first one saves and releases the lock
+        let waited = !waiter.is_finished();
+        credentials("FIRST")
+            .save_to(&file, "single-flight")
+            .unwrap();
+        drop(lock);
+        let second = waiter.join().unwrap().unwrap();
+        //Assert
+        assert!(first.is_none());
+        assert!(waited);
+        assert_eq!(second.unwrap().access_key_id, "FIRST");
+    }
+
+    #[test]
+    fn test_chained_login_with_source_login() {
+        //Arrange
+        let file = temp_credentials_file();
+        let source_logins = Cell::new(0);
+        //Act
+        let result = chained_login(
+            &file,
+            "chained-prod",
+            &chained("chained-corp"),
+            || {
+                source_logins.set(source_logins.get() + 1);
+                Ok(credentials("SOURCE"))
+            },
+            |source| {
+                assert_eq!(source.access_key_id, "SOURCE");
+                Ok(credentials("CHAINED"))
+            },
+        );
+        //Assert
+        assert_eq!(result.unwrap().access_key_id, "CHAINED");
+        assert_eq!(source_logins.get(), 1);
+        let saved = Credentials::load_from(&file, "chained-prod")
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved.access_key_id, "CHAINED");
+    }
+
+    #[test]
+    fn test_chained_login_reusing_source() {
+        //Arrange
+        let file = temp_credentials_file();
+        credentials("SAVED").save_to(&file, "reused-corp").unwrap();
+        //Act
+        let result = chained_login(
+            &file,
+            "reused-prod",
+            &chained("reused-corp"),
+            || panic!("the valid credentials of the source must be reused"),
+            |source| {
+                assert_eq!(source.access_key_id, "SAVED");
+                Ok(credentials("CHAINED"))
+            },
+        );
+        //Assert
+        assert_eq!(result.unwrap().access_key_id, "CHAINED");
+        let saved = Credentials::load_from(&file, "reused-prod")
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved.access_key_id, "CHAINED");
+    }
+}
@@ -1,101 +1,963 @@
 //! Configuration for the AWSAML CLI
-use std::path::PathBuf;
+use std::{env, fmt::Display, ops::RangeInclusive, path::PathBuf, str::FromStr};
 
 use anyhow::Result;
+use url::Url;
 
 use crate::cmd::prompt::{Prompt, Required};
-use awsaml::aws;
+use awsaml::aws::{self, ChainedRole, Partition, Section, StsSettings};
+use awsaml::saml::SessionTag;
+use awsaml::saml::{
+    DEFAULT_IDLE_BROWSER_TIMEOUT, DEFAULT_LOGIN_TIMEOUT, DEFAULT_SILENT_LOGIN_TIMEOUT,
+};
+
+/// The identity providers which awsaml can sign in
+pub const SUPPORTED_IDPS: [&str; 1] = [DEFAULT_IDP];
+pub const DEFAULT_IDP: &str = "entra-id";
+/// The range of the session duration accepted by STS
+const SESSION_DURATION_HOURS: RangeInclusive<i32> = 1..=12;
+/// The keys written to the `[awsaml-session name]` instead of the profile when it is referred
+const SESSION_KEYS: [&str; 4] = [
+    config_keys::IDP,
+    config_keys::ENTRA_ID_TENANT,
+    config_keys::APP_ID_URI,
+    config_keys::CHROME_USER_DATA_DIR,
+];
 
 #[derive(Debug)]
 pub struct Config {
+    pub idp: String,
     pub entra_id_tenant: String,
     pub app_id_uri: String,
     pub session_duration_hours: i32,
     pub chrome_user_data_dir: PathBuf,
+    /// the role to assume when the role name is not given
+    pub saml_role_arn: Option<String>,
+    pub login_timeout_seconds: u64,
+    /// 0 disables the silent login
+    pub silent_login_timeout_seconds: u64,
+    /// attach to a running Chrome instead of launching a new one
+    pub chrome_remote_debugging_url: Option<String>,
+    /// the browser executable, searched in the default locations if None
+    pub chrome_path: Option<PathBuf>,
+    /// extra arguments to launch the browser, separated by whitespace and quoted as in the shell
+    pub chrome_args: Vec<String>,
+    pub chrome_sandbox: bool,
+    pub chrome_idle_timeout_seconds: u64,
+    /// the `[awsaml-session name]` which the profile shares the IdP settings with
+    pub awsaml_session: Option<String>,
+    /// the partition, region and FIPS endpoint of STS
+    pub sts: StsSettings,
+}
+
+/// A profile which assumes `role` with the credentials of the awsaml profile `source_profile`.
+/// The AWS CLI and the SDKs assume the role of such a profile by themselves,
+/// rather than taking the credentials awsaml saves into it.
+#[derive(Debug)]
+pub struct ChainedProfile {
+    pub source_profile: String,
+    pub role: ChainedRole,
+    pub sts: StsSettings,
+}
+
+/// Values of the configuration given by the flags.
+/// Missing values are taken from the environment variables in [`ENV_NAMES`], then from the `[awsaml-session]`, and asked by the prompts at last.
+#[derive(Debug, Default, clap::Args)]
+pub struct ConfigureArgs {
+    /// Entra ID tenant ID (GUID) or domain
+    #[arg(long)]
+    pub tenant: Option<String>,
+    /// App ID URI (Identifier) of the enterprise application
+    #[arg(long)]
+    pub app_id_uri: Option<String>,
+    /// Session duration in hours, from 1 to 12
+    #[arg(long, value_name = "HOURS")]
+    pub session_duration: Option<String>,
+    /// Chrome user data directory, which keeps the IdP session
+    #[arg(long, value_name = "DIR")]
+    pub chrome_user_data_dir: Option<String>,
+    /// Identity provider
+    #[arg(long)]
+    pub idp: Option<String>,
+    /// ARN of the role to assume when `login --role-name` is omitted
+    #[arg(long)]
+    pub role_arn: Option<String>,
+    /// AWS partition: aws, aws-us-gov or aws-cn
+    #[arg(long)]
+    pub partition: Option<String>,
+    /// Region of the STS endpoint
+    #[arg(long)]
+    pub region: Option<String>,
+    /// Call the FIPS endpoint of STS, true when given without a value
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    pub use_fips_endpoint: Option<String>,
+    /// Name of the `[awsaml-session]` section to share the IdP settings with other profiles
+    #[arg(long, value_name = "NAME")]
+    pub awsaml_session: Option<String>,
+    /// Fail instead of prompting for missing values, taking the defaults of the others
+    #[arg(long)]
+    pub non_interactive: bool,
+}
+
+impl ConfigureArgs {
+    /// Take the missing values from the environment variables in [`ENV_NAMES`]
+    pub fn with_env(self) -> Self {
+        let env = |arg: Option<String>, key| arg.or_else(|| env_value(key));
+        ConfigureArgs {
+            tenant: env(self.tenant, config_keys::ENTRA_ID_TENANT),
+            app_id_uri: env(self.app_id_uri, config_keys::APP_ID_URI),
+            session_duration: env(self.session_duration, config_keys::SESSION_DURATION_HOURS),
+            chrome_user_data_dir: env(self.chrome_user_data_dir, config_keys::CHROME_USER_DATA_DIR),
+            idp: env(self.idp, config_keys::IDP),
+            role_arn: env(self.role_arn, config_keys::SAML_ROLE_ARN),
+            partition: env(self.partition, config_keys::AWS_PARTITION),
+            region: env(self.region, config_keys::REGION),
+            use_fips_endpoint: env(self.use_fips_endpoint, config_keys::USE_FIPS_ENDPOINT),
+            awsaml_session: env(self.awsaml_session, config_keys::AWSAML_SESSION),
+            non_interactive: self.non_interactive,
+        }
+    }
+
+    /// Take the missing IdP settings from the existing `[awsaml-session name]`,
+    /// so that configuring another profile of the session asks only the rest.
+    pub fn with_session_defaults(mut self) -> Result<Self> {
+        let Some(name) = &self.awsaml_session else {
+            return Ok(self);
+        };
+        let awsconfig = aws::Config::load()?;
+        if let Some(session) = awsconfig.awsaml_session(name) {
+            let value = |key| session.get(key).map(|v| v.to_string());
+            self.idp = self.idp.or_else(|| value(config_keys::IDP));
+            self.tenant = self.tenant.or_else(|| value(config_keys::ENTRA_ID_TENANT));
+            self.app_id_uri = self.app_id_uri.or_else(|| value(config_keys::APP_ID_URI));
+            self.chrome_user_data_dir = self
+                .chrome_user_data_dir
+                .or_else(|| value(config_keys::CHROME_USER_DATA_DIR));
+        }
+        Ok(self)
+    }
 }
 
 impl Config {
     /// create a new Config<br>
-    /// ask some questions to the user
-    pub fn configure() -> Result<Config> {
-        Ok(Config {
+    /// take the values given by `args`, and ask the others to the user
+    pub fn configure(args: ConfigureArgs) -> Result<Config> {
+        let interactive = !args.non_interactive;
+        let config = Config {
+            idp: Prompt {
+                msg: format!(
+                    "Enter the identity provider ({})",
+                    SUPPORTED_IDPS.join(", ")
+                ),
+                required: Required::Yes(Some(DEFAULT_IDP.to_string())),
+            }
+            .get_value_unless(args.idp, interactive)?,
             entra_id_tenant: Prompt {
                 msg: "Enter the entra ID tenant".to_string(),
                 required: Required::Yes(None),
             }
-            .get_value()?,
+            .get_value_unless(args.tenant, interactive)?,
             app_id_uri: Prompt {
                 msg: "Enter the app ID URI".to_string(),
                 required: Required::Yes(None),
             }
-            .get_value()?,
+            .get_value_unless(args.app_id_uri, interactive)?,
             session_duration_hours: Prompt {
                 msg: "Enter the session duration in hours".to_string(),
                 required: Required::Yes(Some("6".to_string())),
             }
-            .get_value()?,
+            .get_value_unless(args.session_duration, interactive)?,
             chrome_user_data_dir: Prompt {
                 msg: "Enter the Chrome user data directory".to_string(),
                 required: Required::Yes(Some("/tmp".to_string())),
             }
-            .get_value()?,
-        })
+            .get_value_unless(args.chrome_user_data_dir, interactive)?,
+            saml_role_arn: Some(
+                Prompt {
+                    msg: "Enter the ARN of the role to assume by default".to_string(),
+                    required: Required::No,
+                }
+                .get_value_unless::<String>(args.role_arn, interactive)?,
+            )
+            .filter(|v| !v.is_empty()),
+            login_timeout_seconds: Prompt {
+                msg: "Enter the login timeout in seconds".to_string(),
+                required: Required::Yes(Some(DEFAULT_LOGIN_TIMEOUT.as_secs().to_string())),
+            }
+            .get_value_unless(env_value(config_keys::LOGIN_TIMEOUT_SECONDS), interactive)?,
+            silent_login_timeout_seconds: Prompt {
+                msg: "Enter the silent (headless) login timeout in seconds, 0 to disable"
+                    .to_string(),
+                required: Required::Yes(Some(DEFAULT_SILENT_LOGIN_TIMEOUT.as_secs().to_string())),
+            }
+            .get_value_unless(
+                env_value(config_keys::SILENT_LOGIN_TIMEOUT_SECONDS),
+                interactive,
+            )?,
+            chrome_remote_debugging_url: Some(
+                Prompt {
+                    msg: "Enter the remote debugging URL or port of a running Chrome to attach to"
+                        .to_string(),
+                    required: Required::No,
+                }
+                .get_value_unless::<String>(
+                    env_value(config_keys::CHROME_REMOTE_DEBUGGING_URL),
+                    interactive,
+                )?,
+            )
+            .filter(|v| !v.is_empty()),
+            chrome_path: Some(
+                Prompt {
+                    msg: "Enter the Chrome executable path".to_string(),
+                    required: Required::No,
+                }
+                .get_value_unless::<String>(env_value(config_keys::CHROME_PATH), interactive)?,
+            )
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from),
+            chrome_args: split_args(
+                &Prompt {
+                    msg: "Enter the extra Chrome arguments".to_string(),
+                    required: Required::No,
+                }
+                .get_value_unless::<String>(env_value(config_keys::CHROME_ARGS), interactive)?,
+            )?,
+            chrome_sandbox: Prompt {
+                msg: "Launch Chrome with the sandbox".to_string(),
+                required: Required::Yes(Some("true".to_string())),
+            }
+            .get_value_unless(env_value(config_keys::CHROME_SANDBOX), interactive)?,
+            chrome_idle_timeout_seconds: Prompt {
+                msg: "Enter the Chrome idle timeout in seconds".to_string(),
+                required: Required::Yes(Some(DEFAULT_IDLE_BROWSER_TIMEOUT.as_secs().to_string())),
+            }
+            .get_value_unless(
+                env_value(config_keys::CHROME_IDLE_TIMEOUT_SECONDS),
+                interactive,
+            )?,
+            awsaml_session: args.awsaml_session.filter(|v| !v.is_empty()),
+            sts: StsSettings {
+                partition: Prompt {
+                    msg: "Enter the AWS partition (aws, aws-us-gov, aws-cn)".to_string(),
+                    required: Required::Yes(Some(Partition::default().to_string())),
+                }
+                .get_value_unless(args.partition, interactive)?,
+                region: Some(
+                    Prompt {
+                        msg: "Enter the region of the STS endpoint".to_string(),
+                        required: Required::No,
+                    }
+                    .get_value_unless::<String>(args.region, interactive)?,
+                )
+                .filter(|v| !v.is_empty()),
+                use_fips: Prompt {
+                    msg: "Use the FIPS endpoint of STS".to_string(),
+                    required: Required::Yes(Some("false".to_string())),
+                }
+                .get_value_unless(args.use_fips_endpoint, interactive)?,
+                endpoint_url: None,
+            },
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The app ID URI parsed, failing with a config error if it is invalid
+    pub fn app_id_url(&self) -> Result<Url> {
+        Url::parse(&self.app_id_uri)
+            .map_err(|e| config_error(format!("Invalid app ID URI: {}: {}", self.app_id_uri, e)))
+    }
+
+    /// check the values which would fail only at the login
+    fn validate(&self) -> Result<()> {
+        if !SUPPORTED_IDPS.contains(&self.idp.as_str()) {
+            return Err(config_error(format!(
+                "Unsupported identity provider: {}, expected one of {}",
+                self.idp,
+                SUPPORTED_IDPS.join(", ")
+            )));
+        }
+        if !is_tenant(&self.entra_id_tenant) {
+            return Err(config_error(format!(
+                "Invalid tenant: {}, expected a GUID or a domain such as contoso.onmicrosoft.com",
+                self.entra_id_tenant
+            )));
+        }
+        self.app_id_url()?;
+        if !SESSION_DURATION_HOURS.contains(&self.session_duration_hours) {
+            return Err(config_error(format!(
+                "Invalid session duration: {} hours, expected from {} to {}",
+                self.session_duration_hours,
+                SESSION_DURATION_HOURS.start(),
+                SESSION_DURATION_HOURS.end()
+            )));
+        }
+        if let Some(role_arn) = &self.saml_role_arn {
+            if !(role_arn.starts_with("arn:") && role_arn.contains(":role/")) {
+                return Err(config_error(format!("Invalid role ARN: {}", role_arn)));
+            }
+        }
+        self.sts.validate()?;
+        Ok(())
     }
 
     /// save the configuration to a ini file
-    pub fn save(&self, profile: &String) -> Result<()> {
+    pub fn save(&self, profile: &str) -> Result<()> {
         let mut awsconfig = aws::Config::load_or_new()?;
 
-        awsconfig
-            .ini
-            .with_section(Some(profile))
+        // a legacy section written by the former awsaml has these keys
+        let mut section = awsconfig.profile_mut(
+            profile,
+            &[config_keys::ENTRA_ID_TENANT, config_keys::APP_ID_URI],
+        );
+        section
+            .set(config_keys::IDP, &self.idp)
             .set(config_keys::ENTRA_ID_TENANT, &self.entra_id_tenant)
             .set(config_keys::APP_ID_URI, &self.app_id_uri)
             .set(
                 config_keys::SESSION_DURATION_HOURS,
-                &self.session_duration_hours.to_string(),
+                self.session_duration_hours.to_string(),
             )
             .set(
                 config_keys::CHROME_USER_DATA_DIR,
-                &self.chrome_user_data_dir.to_string_lossy().into_owned(),
+                self.chrome_user_data_dir.to_string_lossy(),
+            )
+            .set(
+                config_keys::LOGIN_TIMEOUT_SECONDS,
+                self.login_timeout_seconds.to_string(),
+            )
+            .set(
+                config_keys::SILENT_LOGIN_TIMEOUT_SECONDS,
+                self.silent_login_timeout_seconds.to_string(),
+            );
+        if let Some(role_arn) = &self.saml_role_arn {
+            section.set(config_keys::SAML_ROLE_ARN, role_arn);
+        } else {
+            section.remove(config_keys::SAML_ROLE_ARN);
+        }
+        if let Some(url) = &self.chrome_remote_debugging_url {
+            section.set(config_keys::CHROME_REMOTE_DEBUGGING_URL, url);
+        } else {
+            section.remove(config_keys::CHROME_REMOTE_DEBUGGING_URL);
+        }
+        if let Some(path) = &self.chrome_path {
+            section.set(config_keys::CHROME_PATH, path.to_string_lossy());
+        } else {
+            section.remove(config_keys::CHROME_PATH);
+        }
+        if self.chrome_args.is_empty() {
+            section.remove(config_keys::CHROME_ARGS);
+        } else {
+            section.set(
+                config_keys::CHROME_ARGS,
+                shell_words::join(&self.chrome_args),
+            );
+        }
+        section.set(config_keys::AWS_PARTITION, self.sts.partition.to_string());
+        if let Some(region) = &self.sts.region {
+            section.set(config_keys::REGION, region);
+        } else {
+            section.remove(config_keys::REGION);
+        }
+        section
+            .set(
+                config_keys::USE_FIPS_ENDPOINT,
+                self.sts.use_fips.to_string(),
+            )
+            .set(config_keys::CHROME_SANDBOX, self.chrome_sandbox.to_string())
+            .set(
+                config_keys::CHROME_IDLE_TIMEOUT_SECONDS,
+                self.chrome_idle_timeout_seconds.to_string(),
             );
+        if let Some(name) = &self.awsaml_session {
+            section.set(config_keys::AWSAML_SESSION, name);
+            for key in SESSION_KEYS {
+                section.remove(key);
+            }
+            awsconfig
+                .awsaml_session_mut(name)
+                .set(config_keys::IDP, &self.idp)
+                .set(config_keys::ENTRA_ID_TENANT, &self.entra_id_tenant)
+                .set(config_keys::APP_ID_URI, &self.app_id_uri)
+                .set(
+                    config_keys::CHROME_USER_DATA_DIR,
+                    self.chrome_user_data_dir.to_string_lossy(),
+                );
+        } else {
+            section.remove(config_keys::AWSAML_SESSION);
+        }
         awsconfig.save()?;
         println!("Configuration saved to {}", &awsconfig.file_path);
         Ok(())
     }
 
     pub fn load(profile: &String) -> Result<Config> {
-        let awsconfig = aws::Config::load_or_new()?;
+        let awsconfig = aws::Config::load()?;
 
-        if let Some(section) = awsconfig.ini.section(Some(profile)) {
+        if let Some(section) = awsconfig.profile(profile) {
+            let awsaml_session = env_value(config_keys::AWSAML_SESSION).or_else(|| {
+                section
+                    .get(config_keys::AWSAML_SESSION)
+                    .map(|v| v.to_string())
+            });
+            let session = match &awsaml_session {
+                Some(name) => Some(awsconfig.awsaml_session(name).ok_or_else(|| {
+                    config_error(format!(
+                        "awsaml-session[{}] referred by Profile[{}] not found",
+                        name, profile
+                    ))
+                })?),
+                None => None,
+            };
+            let section = ProfileValues {
+                profile: section,
+                session,
+            };
             Ok(Config {
-                entra_id_tenant: section
-                    .get(config_keys::ENTRA_ID_TENANT)
-                    .unwrap_or("")
-                    .to_string(),
-                app_id_uri: section
-                    .get(config_keys::APP_ID_URI)
-                    .unwrap_or("")
-                    .to_string(),
-                session_duration_hours: section
-                    .get(config_keys::SESSION_DURATION_HOURS)
-                    .unwrap_or("0")
-                    .parse::<i32>()?,
-                chrome_user_data_dir: section
-                    .get(config_keys::CHROME_USER_DATA_DIR)
-                    .unwrap_or("")
-                    .to_string()
+                idp: env_or(section, config_keys::IDP).unwrap_or_else(|| DEFAULT_IDP.to_string()),
+                entra_id_tenant: env_or(section, config_keys::ENTRA_ID_TENANT).unwrap_or_default(),
+                app_id_uri: env_or(section, config_keys::APP_ID_URI).unwrap_or_default(),
+                session_duration_hours: parse_value(section, config_keys::SESSION_DURATION_HOURS)?
+                    .unwrap_or(0),
+                chrome_user_data_dir: env_or(section, config_keys::CHROME_USER_DATA_DIR)
+                    .unwrap_or_default()
                     .into(),
+                saml_role_arn: env_or(section, config_keys::SAML_ROLE_ARN),
+                login_timeout_seconds: parse_value(section, config_keys::LOGIN_TIMEOUT_SECONDS)?
+                    .unwrap_or(DEFAULT_LOGIN_TIMEOUT.as_secs()),
+                silent_login_timeout_seconds: parse_value(
+                    section,
+                    config_keys::SILENT_LOGIN_TIMEOUT_SECONDS,
+                )?
+                .unwrap_or(DEFAULT_SILENT_LOGIN_TIMEOUT.as_secs()),
+                chrome_remote_debugging_url: env_or(
+                    section,
+                    config_keys::CHROME_REMOTE_DEBUGGING_URL,
+                ),
+                chrome_path: env_or(section, config_keys::CHROME_PATH).map(PathBuf::from),
+                chrome_args: split_args(
+                    &env_or(section, config_keys::CHROME_ARGS).unwrap_or_default(),
+                )?,
+                chrome_sandbox: parse_value(section, config_keys::CHROME_SANDBOX)?.unwrap_or(true),
+                chrome_idle_timeout_seconds: parse_value(
+                    section,
+                    config_keys::CHROME_IDLE_TIMEOUT_SECONDS,
+                )?
+                .unwrap_or(DEFAULT_IDLE_BROWSER_TIMEOUT.as_secs()),
+                awsaml_session,
+                sts: sts_settings(section)?,
             })
         } else {
-            Err(anyhow::anyhow!("Profile[{}] not found", profile))
+            Err(config_error(format!("Profile[{}] not found", profile)))
+        }
+    }
+}
+
+impl ChainedProfile {
+    /// The chained role of the profile, `None` if it has no `source_profile`
+    pub fn load(profile: &str) -> Result<Option<ChainedProfile>> {
+        let awsconfig = aws::Config::load()?;
+        match awsconfig.profile(profile) {
+            Some(section) => Self::from_section(profile, section),
+            None => Ok(None),
         }
     }
+
+    fn from_section(profile: &str, section: Section) -> Result<Option<ChainedProfile>> {
+        let sts = sts_settings(ProfileValues {
+            profile: section,
+            session: None,
+        })?;
+        let Some(source_profile) = section.get(config_keys::SOURCE_PROFILE) else {
+            return Ok(None);
+        };
+        if source_profile == profile {
+            return Err(config_error(format!(
+                "Profile[{}] cannot be its own source_profile",
+                profile
+            )));
+        }
+        let role_arn = section.get(config_keys::ROLE_ARN).ok_or_else(|| {
+            config_error(format!(
+                "Profile[{}] has source_profile but no role_arn",
+                profile
+            ))
+        })?;
+        Ok(Some(ChainedProfile {
+            source_profile: source_profile.to_string(),
+            role: ChainedRole {
+                role_arn: role_arn.to_string(),
+                role_session_name: section
+                    .get(config_keys::ROLE_SESSION_NAME)
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| format!("awsaml-{}", chrono::Utc::now().timestamp())),
+                external_id: section.get(config_keys::EXTERNAL_ID).map(|v| v.to_string()),
+                duration_seconds: section
+                    .get(config_keys::DURATION_SECONDS)
+                    .map(|v| {
+                        v.parse::<i32>().map_err(|e| {
+                            config_error(format!(
+                                "Invalid duration_seconds of Profile[{}]: {}: {}",
+                                profile, v, e
+                            ))
+                        })
+                    })
+                    .transpose()?,
+                session_tags: parse_tags(section.get(config_keys::SESSION_TAGS).unwrap_or(""))?,
+                transitive_tag_keys: split_list(
+                    section.get(config_keys::TRANSITIVE_TAG_KEYS).unwrap_or(""),
+                ),
+            },
+            sts,
+        }))
+    }
+}
+
+/// The values of a profile, falling back on its `[awsaml-session name]`
+#[derive(Clone, Copy)]
+struct ProfileValues<'a> {
+    profile: Section<'a>,
+    session: Option<Section<'a>>,
+}
+
+impl<'a> ProfileValues<'a> {
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.profile
+            .get(key)
+            .or_else(|| self.session.and_then(|session| session.get(key)))
+    }
+}
+
+/// Environment variables of the keys of the profile written by `configure`.
+///
+/// Each stands for its key wherever the key is read:
+/// `configure` saves it into the profile, and the other commands take it over the saved value.
+/// The keys of the role chaining of the AWS CLI, such as `source_profile`, have none.
+const ENV_NAMES: [(&str, &str); 17] = [
+    (config_keys::IDP, "AWSAML_IDP"),
+    (config_keys::ENTRA_ID_TENANT, "AWSAML_ENTRA_ID_TENANT"),
+    (config_keys::APP_ID_URI, "AWSAML_APP_ID_URI"),
+    (
+        config_keys::SESSION_DURATION_HOURS,
+        "AWSAML_SESSION_DURATION_HOURS",
+    ),
+    (
+        config_keys::CHROME_USER_DATA_DIR,
+        "AWSAML_CHROME_USER_DATA_DIR",
+    ),
+    (config_keys::SAML_ROLE_ARN, "AWSAML_SAML_ROLE_ARN"),
+    (
+        config_keys::LOGIN_TIMEOUT_SECONDS,
+        "AWSAML_LOGIN_TIMEOUT_SECONDS",
+    ),
+    (
+        config_keys::SILENT_LOGIN_TIMEOUT_SECONDS,
+        "AWSAML_SILENT_LOGIN_TIMEOUT_SECONDS",
+    ),
+    (
+        config_keys::CHROME_REMOTE_DEBUGGING_URL,
+        "AWSAML_CHROME_REMOTE_DEBUGGING_URL",
+    ),
+    (config_keys::CHROME_PATH, "AWSAML_CHROME_PATH"),
+    (config_keys::CHROME_ARGS, "AWSAML_CHROME_ARGS"),
+    (config_keys::CHROME_SANDBOX, "AWSAML_CHROME_SANDBOX"),
+    (
+        config_keys::CHROME_IDLE_TIMEOUT_SECONDS,
+        "AWSAML_CHROME_IDLE_TIMEOUT_SECONDS",
+    ),
+    (config_keys::AWSAML_SESSION, "AWSAML_SESSION"),
+    (config_keys::AWS_PARTITION, "AWSAML_PARTITION"),
+    (config_keys::REGION, "AWSAML_REGION"),
+    (config_keys::USE_FIPS_ENDPOINT, "AWSAML_USE_FIPS_ENDPOINT"),
+];
+
+/// The name of the environment variable of the key in [`ENV_NAMES`]
+fn env_name(key: &str) -> Option<&'static str> {
+    ENV_NAMES
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, name)| *name)
+}
+
+/// The value of the environment variable of the key, e.g. `AWSAML_CHROME_PATH` of `chrome_path`
+fn env_value(key: &str) -> Option<String> {
+    env::var(env_name(key)?).ok()
+}
+
+/// The value of the key in the profile or its session,
+/// which is overridden by its environment variable in [`ENV_NAMES`].
+fn env_or(section: ProfileValues, key: &str) -> Option<String> {
+    env_value(key).or_else(|| section.get(key).map(|v| v.to_string()))
+}
+
+/// The value of [`env_or`] parsed, failing with a config error if it is invalid
+fn parse_value<T>(section: ProfileValues, key: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    env_or(section, key)
+        .map(|v| {
+            v.parse::<T>()
+                .map_err(|e| config_error(format!("Invalid {}: {}: {}", key, v, e)))
+        })
+        .transpose()
+}
+
+/// Split the arguments as the shell does,
+/// e.g. `--user-agent="Mozilla/5.0 (X11)" --lang=ja` into two arguments
+fn split_args(args: &str) -> Result<Vec<String>> {
+    shell_words::split(args)
+        .map_err(|e| config_error(format!("Invalid Chrome arguments: {}: {}", args, e)))
+}
+
+/// How to call STS for the profile.
+/// The partition defaults to the one of the region.
+fn sts_settings(section: ProfileValues) -> Result<StsSettings> {
+    let region = env_or(section, config_keys::REGION);
+    let partition = match env_or(section, config_keys::AWS_PARTITION) {
+        Some(partition) => partition.parse()?,
+        None => region
+            .as_deref()
+            .map(Partition::of_region)
+            .unwrap_or_default(),
+    };
+    let sts = StsSettings {
+        region,
+        partition,
+        use_fips: parse_value(section, config_keys::USE_FIPS_ENDPOINT)?.unwrap_or(false),
+        endpoint_url: None,
+    };
+    sts.validate()?;
+    Ok(sts)
+}
+
+/// Split a comma separated list, e.g. `Project,CostCenter`
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+/// Parse the session tags written as `Project=alpha,CostCenter=42`
+fn parse_tags(tags: &str) -> Result<Vec<SessionTag>> {
+    split_list(tags)
+        .iter()
+        .map(|tag| match tag.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(SessionTag {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            }),
+            _ => Err(config_error(format!(
+                "Invalid session tag: {}, expected Key=Value",
+                tag
+            ))),
+        })
+        .collect()
+}
+
+fn config_error(msg: String) -> anyhow::Error {
+    awsaml::Error::Config(msg).into()
+}
+
+/// Whether the value is a tenant ID (GUID) or a domain of Entra ID
+fn is_tenant(value: &str) -> bool {
+    let is_guid = value
+        .split('-')
+        .map(|group| group.len())
+        .eq([8, 4, 4, 4, 12])
+        && value.chars().all(|c| c == '-' || c.is_ascii_hexdigit());
+    let is_domain = value.contains('.')
+        && value.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    is_guid || is_domain
 }
 
 mod config_keys {
+    pub const IDP: &str = "idp";
     pub const ENTRA_ID_TENANT: &str = "entra_id_tenant";
     pub const APP_ID_URI: &str = "app_id_uri";
     pub const SESSION_DURATION_HOURS: &str = "session_duration_hours";
     pub const CHROME_USER_DATA_DIR: &str = "chrome_user_data_dir";
+    pub const SAML_ROLE_ARN: &str = "saml_role_arn";
+    pub const LOGIN_TIMEOUT_SECONDS: &str = "login_timeout_seconds";
+    pub const SILENT_LOGIN_TIMEOUT_SECONDS: &str = "silent_login_timeout_seconds";
+    pub const CHROME_REMOTE_DEBUGGING_URL: &str = "chrome_remote_debugging_url";
+    pub const CHROME_PATH: &str = "chrome_path";
+    pub const CHROME_ARGS: &str = "chrome_args";
+    pub const CHROME_SANDBOX: &str = "chrome_sandbox";
+    pub const CHROME_IDLE_TIMEOUT_SECONDS: &str = "chrome_idle_timeout_seconds";
+    pub const AWSAML_SESSION: &str = "awsaml_session";
+    pub const AWS_PARTITION: &str = "aws_partition";
+    // shared with the AWS CLI
+    pub const REGION: &str = "region";
+    pub const USE_FIPS_ENDPOINT: &str = "use_fips_endpoint";
+    // the same keys as the AWS CLI's role chaining
+    pub const SOURCE_PROFILE: &str = "source_profile";
+    pub const ROLE_ARN: &str = "role_arn";
+    pub const EXTERNAL_ID: &str = "external_id";
+    pub const ROLE_SESSION_NAME: &str = "role_session_name";
+    pub const DURATION_SECONDS: &str = "duration_seconds";
+    pub const SESSION_TAGS: &str = "session_tags";
+    pub const TRANSITIVE_TAG_KEYS: &str = "transitive_tag_keys";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> ConfigureArgs {
+        ConfigureArgs {
+            tenant: Some("contoso.onmicrosoft.com".to_string()),
+            app_id_uri: Some("https://signin.aws.amazon.com/saml#1".to_string()),
+            non_interactive: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_configure_non_interactive() {
+        //Arrange
+        let args = ConfigureArgs {
+            session_duration: Some("12".to_string()),
+            role_arn: Some("arn:aws:iam::123456789012:role/Admin".to_string()),
+            ..args()
+        };
+        //Act
+        let config = Config::configure(args).unwrap();
+        //Assert
+        assert_eq!(config.idp, DEFAULT_IDP);
+        assert_eq!(config.session_duration_hours, 12);
+        assert_eq!(
+            config.saml_role_arn.as_deref(),
+            Some("arn:aws:iam::123456789012:role/Admin")
+        );
+        assert_eq!(
+            config.login_timeout_seconds,
+            DEFAULT_LOGIN_TIMEOUT.as_secs()
+        );
+        assert_eq!(config.chrome_path, None);
+    }
+
+    #[test]
+    fn test_configure_non_interactive_missing() {
+        let args = ConfigureArgs {
+            tenant: None,
+            ..args()
+        };
+        assert!(matches!(
+            Config::configure(args).unwrap_err().downcast_ref(),
+            Some(awsaml::Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_configure_invalid() {
+        for args in [
+            ConfigureArgs {
+                tenant: Some("contoso".to_string()),
+                ..args()
+            },
+            ConfigureArgs {
+                app_id_uri: Some("signin.aws.amazon.com".to_string()),
+                ..args()
+            },
+            ConfigureArgs {
+                session_duration: Some("13".to_string()),
+                ..args()
+            },
+            ConfigureArgs {
+                idp: Some("okta".to_string()),
+                ..args()
+            },
+            ConfigureArgs {
+                partition: Some("aws-cn".to_string()),
+                region: Some("us-east-1".to_string()),
+                ..args()
+            },
+            ConfigureArgs {
+                partition: Some("aws-cn".to_string()),
+                use_fips_endpoint: Some("true".to_string()),
+                ..args()
+            },
+        ] {
+            let result = Config::configure(args);
+            assert!(matches!(
+                result.unwrap_err().downcast_ref(),
+                Some(awsaml::Error::Config(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_profile_values() {
+        //Arrange
+        let ini = aws::IniFile::parse(
+            "[awsaml-session corp]\nentra_id_tenant = contoso.onmicrosoft.com\nidp = entra-id\n\n[profile dev]\nawsaml_session = corp\nidp = other\n",
+        );
+        let values = ProfileValues {
+            profile: ini.section("profile dev").unwrap(),
+            session: ini.section("awsaml-session corp"),
+        };
+        //Act & Assert
+        assert_eq!(
+            values.get("entra_id_tenant"),
+            Some("contoso.onmicrosoft.com")
+        );
+        assert_eq!(values.get("idp"), Some("other"));
+        assert_eq!(values.get("app_id_uri"), None);
+    }
+
+    #[test]
+    fn test_env_name() {
+        assert_eq!(
+            env_name(config_keys::AWSAML_SESSION),
+            Some("AWSAML_SESSION")
+        );
+        assert_eq!(
+            env_name(config_keys::AWS_PARTITION),
+            Some("AWSAML_PARTITION")
+        );
+        assert_eq!(
+            env_name(config_keys::ENTRA_ID_TENANT),
+            Some("AWSAML_ENTRA_ID_TENANT")
+        );
+        assert_eq!(env_name(config_keys::SOURCE_PROFILE), None);
+    }
+
+    #[test]
+    fn test_parse_value_invalid() {
+        //Arrange
+        let ini = aws::IniFile::parse("[profile dev]\nlogin_timeout_seconds = soon\n");
+        let values = ProfileValues {
+            profile: ini.section("profile dev").unwrap(),
+            session: None,
+        };
+        //Act
+        let result = parse_value::<u64>(values, config_keys::LOGIN_TIMEOUT_SECONDS);
+        //Assert
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(awsaml::Error::Config(_))
+        ));
+        assert_eq!(
+            parse_value::<bool>(values, config_keys::CHROME_SANDBOX).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_chained_profile() {
+        //Arrange
+        let ini = aws::IniFile::parse(
+            "[profile prod]\nsource_profile = corp\nrole_arn = arn:aws:iam::123456789012:role/Deploy\nexternal_id = 42\nduration_seconds = 900\nsession_tags = Project=alpha, CostCenter=42\ntransitive_tag_keys = Project\n\n[profile corp]\nidp = entra-id\n",
+        );
+        //Act
+        let prod = ChainedProfile::from_section("prod", ini.section("profile prod").unwrap());
+        let corp = ChainedProfile::from_section("corp", ini.section("profile corp").unwrap());
+        //Assert
+        let prod = prod.unwrap().unwrap();
+        assert_eq!(prod.source_profile, "corp");
+        assert_eq!(prod.role.role_arn, "arn:aws:iam::123456789012:role/Deploy");
+        assert_eq!(prod.role.external_id.as_deref(), Some("42"));
+        assert_eq!(prod.role.duration_seconds, Some(900));
+        assert_eq!(
+            prod.role.session_tags,
+            vec![
+                SessionTag {
+                    key: "Project".to_string(),
+                    value: "alpha".to_string(),
+                },
+                SessionTag {
+                    key: "CostCenter".to_string(),
+                    value: "42".to_string(),
+                },
+            ]
+        );
+        assert_eq!(prod.role.transitive_tag_keys, vec!["Project".to_string()]);
+        assert!(prod.role.role_session_name.starts_with("awsaml-"));
+        assert_eq!(prod.sts.partition, Partition::Aws);
+        assert!(corp.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_chained_profile_invalid_duration() {
+        //Arrange
+        let ini = aws::IniFile::parse(
+            "[profile prod]\nsource_profile = corp\nrole_arn = arn:aws:iam::123456789012:role/Deploy\nduration_seconds = 1h\n",
+        );
+        //Act
+        let result = ChainedProfile::from_section("prod", ini.section("profile prod").unwrap());
+        //Assert
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(awsaml::Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_sts_settings() {
+        //Arrange
+        let ini = aws::IniFile::parse(
+            "[profile gov]\nregion = us-gov-east-1\nuse_fips_endpoint = true\n\n[profile cn]\naws_partition = aws-cn\nuse_fips_endpoint = true\n",
+        );
+        let values = |name| ProfileValues {
+            profile: ini.section(name).unwrap(),
+            session: None,
+        };
+        //Act
+        let gov = sts_settings(values("profile gov")).unwrap();
+        let cn = sts_settings(values("profile cn"));
+        //Assert
+        assert_eq!(gov.partition, Partition::AwsUsGov);
+        assert_eq!(gov.region.as_deref(), Some("us-gov-east-1"));
+        assert!(gov.use_fips);
+        assert!(cn.is_err());
+    }
+
+    #[test]
+    fn test_parse_tags_invalid() {
+        assert!(parse_tags("Project").is_err());
+        assert!(parse_tags("=alpha").is_err());
+        assert!(parse_tags("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_split_args() {
+        //Arrange
+        let args =
+            r#"--user-agent="Mozilla/5.0 (X11)"  --lang=ja '--proxy-server=http://proxy:8080'"#;
+        //Act
+        let result = split_args(args).unwrap();
+        //Assert
+        assert_eq!(
+            result,
+            vec![
+                "--user-agent=Mozilla/5.0 (X11)",
+                "--lang=ja",
+                "--proxy-server=http://proxy:8080"
+            ]
+        );
+        assert_eq!(split_args(&shell_words::join(&result)).unwrap(), result);
+        assert!(split_args(r#"--user-agent="Mozilla"#).is_err());
+    }
+
+    #[test]
+    fn test_is_tenant() {
+        assert!(is_tenant("72f988bf-86f1-41af-91ab-2d7cd011db47"));
+        assert!(is_tenant("contoso.onmicrosoft.com"));
+        assert!(!is_tenant("72f988bf-86f1-41af-91ab"));
+        assert!(!is_tenant("contoso..com"));
+    }
 }
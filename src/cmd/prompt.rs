@@ -25,6 +25,32 @@ impl Prompt {
         T::from_str(&input).map_err(|e| Error::new(e))
     }
 
+    /// The given value if any, otherwise the value entered by the user.
+    /// Without `interactive`, the default value is taken instead of asking,
+    /// and a missing required value is an error.
+    pub fn get_value_unless<T>(&self, given: Option<String>, interactive: bool) -> Result<T>
+    where
+        T: FromStr,
+        <T as FromStr>::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let input = match (given, &self.required) {
+            (Some(given), _) => given,
+            (None, _) if interactive => return self.get_value(),
+            (None, Required::Yes(Some(default))) => default.clone(),
+            (None, Required::Yes(None)) => {
+                return Err(awsaml::Error::Config(format!(
+                    "Input is required in the non-interactive mode: {}",
+                    self.msg
+                ))
+                .into())
+            }
+            (None, Required::No) => String::new(),
+        };
+        T::from_str(input.trim()).map_err(|e| {
+            awsaml::Error::Config(format!("Invalid value {:?}: {}: {}", input, self.msg, e)).into()
+        })
+    }
+
     fn read_input(&self) -> Result<String> {
         self.print_input_msg();
         // Read input from the user
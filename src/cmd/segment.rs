@@ -0,0 +1,152 @@
+//! A segment of the shell prompt showing the awsaml session of the profile
+use awsaml::aws::Credentials;
+use chrono::{Duration, Utc};
+
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+const RED: &str = "31";
+
+/// The shell printing the segment, which decides how the colours are escaped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    /// starship's custom command module, which marks the ANSI colours of the output itself
+    Starship,
+    /// raw ANSI colours
+    Plain,
+}
+
+/// The segment such as `dev 42m`, empty if the profile has no credentials of awsaml.
+/// It is yellow when less than `warn` remains, and red once expired.
+pub fn segment(
+    profile: &str,
+    credentials: Option<&Credentials>,
+    warn: Duration,
+    shell: Shell,
+) -> String {
+    let Some(credentials) = credentials else {
+        return String::new();
+    };
+    let remaining = credentials.expiration - Utc::now();
+    let (text, color) = if remaining <= Duration::zero() {
+        (format!("{} expired", profile), RED)
+    } else if remaining < warn {
+        (format!("{} {}", profile, compact(remaining)), YELLOW)
+    } else {
+        (format!("{} {}", profile, compact(remaining)), GREEN)
+    };
+    paint(&text, color, shell)
+}
+
+/// The snippet to put in the configuration of the shell
+pub fn snippet(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => {
+            r#"# ~/.bashrc
+__awsaml_segment() { local s; s=$(awsaml prompt-segment --shell bash) && [ -n "$s" ] && printf '%s ' "$s"; }
+PS1='$(__awsaml_segment)'"$PS1"
+"#
+        }
+        Shell::Zsh => {
+            r#"# ~/.zshrc
+setopt PROMPT_SUBST
+__awsaml_segment() { local s; s=$(awsaml prompt-segment --shell zsh) && [[ -n $s ]] && print -rn -- "$s "; }
+PROMPT='$(__awsaml_segment)'"$PROMPT"
+"#
+        }
+        Shell::Fish => {
+            r#"# ~/.config/fish/config.fish
+functions -q __awsaml_fish_prompt; or functions -c fish_prompt __awsaml_fish_prompt
+function fish_prompt
+    set -l segment (awsaml prompt-segment --shell fish)
+    test -n "$segment"; and printf '%s ' $segment
+    __awsaml_fish_prompt
+end
+"#
+        }
+        Shell::Starship => {
+            r#"# ~/.config/starship.toml
+[custom.awsaml]
+command = "awsaml prompt-segment --shell starship"
+when = true
+# the output carries its own colour, which a style would override
+format = "$output "
+"#
+        }
+        Shell::Plain => {
+            r#"# print the segment with the ANSI colours, e.g. in a status bar
+awsaml prompt-segment
+"#
+        }
+    }
+}
+
+/// The remaining time such as `42m` or `1h05m`
+fn compact(remaining: Duration) -> String {
+    let minutes = remaining.num_minutes();
+    if minutes < 60 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h{:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// Colour the text, marking the escapes as zero-width for the shell
+fn paint(text: &str, color: &str, shell: Shell) -> String {
+    let (start, end, text) = match shell {
+        // \[ and \] are not interpreted in the output of a command substitution
+        Shell::Bash => ("\x01", "\x02", text.to_string()),
+        Shell::Zsh => ("%{", "%}", text.replace('%', "%%")),
+        // starship marks the escapes in its output as zero-width for the shell
+        Shell::Fish | Shell::Starship | Shell::Plain => ("", "", text.to_string()),
+    };
+    format!(
+        "{start}\x1b[{color}m{end}{text}{start}\x1b[0m{end}",
+        start = start,
+        end = end,
+        color = color,
+        text = text
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(remaining: Duration) -> Credentials {
+        Credentials {
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+            session_token: String::new(),
+            // a few seconds of slack for the test to run
+            expiration: Utc::now() + remaining + Duration::seconds(5),
+            assumed_role_arn: None,
+            source_identity: None,
+        }
+    }
+
+    #[test]
+    fn test_segment() {
+        //Arrange
+        let warn = Duration::minutes(15);
+        let fresh = credentials(Duration::minutes(65));
+        let expiring = credentials(Duration::minutes(10));
+        let expired = credentials(Duration::minutes(-1));
+        //Act & Assert
+        assert_eq!(
+            segment("dev", Some(&fresh), warn, Shell::Starship),
+            "\x1b[32mdev 1h05m\x1b[0m"
+        );
+        assert_eq!(
+            segment("dev", Some(&expiring), warn, Shell::Bash),
+            "\x01\x1b[33m\x02dev 10m\x01\x1b[0m\x02"
+        );
+        assert_eq!(
+            segment("100%", Some(&expired), warn, Shell::Zsh),
+            "%{\x1b[31m%}100%% expired%{\x1b[0m%}"
+        );
+        assert_eq!(segment("dev", None, warn, Shell::Plain), "");
+    }
+}
@@ -0,0 +1,176 @@
+//! Status of the sessions saved by awsaml
+use awsaml::aws::{CallerIdentity, Credentials};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+
+/// The session of a profile, shown by `whoami`
+#[derive(Debug, Serialize)]
+pub struct SessionStatus {
+    pub profile: String,
+    pub role_arn: Option<String>,
+    pub account: Option<String>,
+    pub session_name: Option<String>,
+    pub source_identity: Option<String>,
+    pub expiration: String,
+    /// negative once expired
+    pub remaining_seconds: i64,
+    /// whether STS accepted the credentials, `None` if not verified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+    /// the ARN returned by STS, or the reason of the failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<String>,
+}
+
+impl SessionStatus {
+    pub fn new(profile: &str, credentials: &Credentials) -> Self {
+        SessionStatus {
+            profile: profile.to_string(),
+            role_arn: credentials.role_arn(),
+            account: credentials.account().map(|v| v.to_string()),
+            session_name: credentials.session_name().map(|v| v.to_string()),
+            source_identity: credentials.source_identity.clone(),
+            expiration: credentials
+                .expiration
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            remaining_seconds: (credentials.expiration - Utc::now()).num_seconds(),
+            verified: None,
+            verification: None,
+        }
+    }
+
+    /// Record the result of GetCallerIdentity
+    pub fn verified_by(&mut self, identity: awsaml::Result<CallerIdentity>) {
+        match identity {
+            Ok(identity) => {
+                self.verified = Some(true);
+                self.verification = Some(identity.arn);
+            }
+            Err(e) => {
+                self.verified = Some(false);
+                self.verification = Some(e.to_string());
+            }
+        }
+    }
+
+    /// The status as the lines of `name: value`
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Profile: {}", self.profile),
+            format!("Role ARN: {}", or_dash(&self.role_arn)),
+            format!("Account: {}", or_dash(&self.account)),
+            format!("Session name: {}", or_dash(&self.session_name)),
+            format!("Source identity: {}", or_dash(&self.source_identity)),
+            format!("Expiration: {}", self.expiration),
+            format!("Remaining: {}", format_remaining(self.remaining_seconds)),
+        ];
+        if let (Some(verified), Some(verification)) = (self.verified, &self.verification) {
+            let result = if verified { "yes" } else { "no" };
+            lines.push(format!("Verified: {} ({})", result, verification));
+        }
+        lines
+    }
+}
+
+/// The statuses as a table with a header
+pub fn to_table(statuses: &[SessionStatus]) -> String {
+    let verified = statuses.iter().any(|status| status.verified.is_some());
+    let mut rows = vec![vec![
+        "PROFILE",
+        "ROLE",
+        "ACCOUNT",
+        "SESSION",
+        "EXPIRATION",
+        "REMAINING",
+    ]
+    .into_iter()
+    .map(|v| v.to_string())
+    .collect::<Vec<_>>()];
+    if verified {
+        rows[0].push("VERIFIED".to_string());
+    }
+    for status in statuses {
+        let mut row = vec![
+            status.profile.clone(),
+            or_dash(&status.role_arn).to_string(),
+            or_dash(&status.account).to_string(),
+            or_dash(&status.session_name).to_string(),
+            status.expiration.clone(),
+            format_remaining(status.remaining_seconds),
+        ];
+        if verified {
+            row.push(match status.verified {
+                Some(true) => "yes".to_string(),
+                Some(false) => "no".to_string(),
+                None => "-".to_string(),
+            });
+        }
+        rows.push(row);
+    }
+
+    let widths = (0..rows[0].len())
+        .map(|i| rows.iter().map(|row| row[i].len()).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The remaining time such as `1h05m`, or `expired`
+pub fn format_remaining(seconds: i64) -> String {
+    if seconds <= 0 {
+        return "expired".to_string();
+    }
+    let minutes = seconds / 60;
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+fn or_dash(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_remaining() {
+        assert_eq!(format_remaining(3900), "1h05m");
+        assert_eq!(format_remaining(59), "0h00m");
+        assert_eq!(format_remaining(0), "expired");
+        assert_eq!(format_remaining(-10), "expired");
+    }
+
+    #[test]
+    fn test_to_table() {
+        //Arrange
+        let status = SessionStatus {
+            profile: "dev".to_string(),
+            role_arn: Some("arn:aws:iam::123456789012:role/Admin".to_string()),
+            account: Some("123456789012".to_string()),
+            session_name: None,
+            source_identity: None,
+            expiration: "2030-01-01T00:00:00Z".to_string(),
+            remaining_seconds: 3600,
+            verified: None,
+            verification: None,
+        };
+        //Act
+        let table = to_table(&[status]);
+        //Assert
+        assert_eq!(
+            table,
+            "PROFILE  ROLE                                  ACCOUNT       SESSION  EXPIRATION            REMAINING\n\
+             dev      arn:aws:iam::123456789012:role/Admin  123456789012  -        2030-01-01T00:00:00Z  1h00m"
+        );
+    }
+}
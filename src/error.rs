@@ -0,0 +1,85 @@
+//! Errors of the awsaml library.
+use std::time::Duration;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors of the awsaml library.
+/// Each variant is mapped to its own exit code by the CLI.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The configuration is missing or invalid
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// The IdP refused the login
+    #[error(transparent)]
+    Idp(#[from] IdpError),
+    /// The browser could not be launched or driven
+    #[error("Browser error: {0}")]
+    Browser(String),
+    /// The SAML message could not be decoded
+    #[error("Failed to decode the SAML message: {0}")]
+    SamlDecode(String),
+    /// The SAML message is not what is expected
+    #[error("Invalid SAML message: {0}")]
+    SamlValidation(String),
+    /// STS refused to issue the credentials
+    #[error("STS error: {0}")]
+    Sts(String),
+    /// The credentials could not be saved or loaded
+    #[error("Credentials store error: {0}")]
+    CredentialsStore(String),
+    /// The login was not completed within the timeout
+    #[error("Login timed out after {} seconds", .0.as_secs())]
+    Timeout(Duration),
+    /// The login was given up by the user
+    #[error(transparent)]
+    Cancelled(#[from] CancelReason),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    /// Wrap an error from the browser automation
+    pub(crate) fn browser(e: impl std::fmt::Display) -> Self {
+        Error::Browser(e.to_string())
+    }
+
+    /// Wrap an error while decoding a SAML message
+    pub(crate) fn saml_decode(e: impl std::fmt::Display) -> Self {
+        Error::SamlDecode(e.to_string())
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum IdpError {
+    /// The IdP returned an error page, with the description extracted from the page if any
+    #[error("The IdP returned an error page: {status} {url}{}", .message.as_ref().map(|m| format!(": {}", m)).unwrap_or_default())]
+    ErrorPage {
+        status: u32,
+        url: String,
+        message: Option<String>,
+    },
+    /// The IdP requires the user to sign in, which is impossible without a window
+    #[error("The IdP requires the user to sign in")]
+    InteractionRequired,
+    /// The IdP answered the LogoutRequest with a status other than the success
+    #[error("The IdP did not end the session: {status}{}", .message.as_ref().map(|m| format!(": {}", m)).unwrap_or_default())]
+    LogoutFailed {
+        status: String,
+        message: Option<String>,
+    },
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CancelReason {
+    /// The user interrupted the login (e.g. by Ctrl-C)
+    #[error("The login was cancelled")]
+    Interrupted,
+    /// The user closed the browser or the login tab
+    #[error("The browser was closed before the login completed")]
+    BrowserClosed,
+}
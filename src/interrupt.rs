@@ -0,0 +1,105 @@
+//! Cancellation of the browser login by Ctrl-C.
+use std::io;
+use std::sync::{atomic::AtomicBool, Arc};
+
+/// While alive, the first Ctrl-C sets the cancel flag instead of terminating the process,
+/// and the second one exits with 130 as the shell does.
+/// The former handling of SIGINT, the default one usually, is restored when dropped,
+/// so Ctrl-C outside the guarded wait terminates the process as usual.
+pub(crate) struct CtrlCGuard {
+    #[cfg(unix)]
+    previous_action: libc::sigaction,
+    #[cfg(unix)]
+    previous_flag: *mut AtomicBool,
+    /// Kept alive while the signal handler may point to it
+    _cancelled: Arc<AtomicBool>,
+}
+
+#[cfg(unix)]
+static CANCELLED: std::sync::atomic::AtomicPtr<AtomicBool> =
+    std::sync::atomic::AtomicPtr::new(std::ptr::null_mut());
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_: libc::c_int) {
+    use std::sync::atomic::Ordering;
+    let cancelled = CANCELLED.load(Ordering::SeqCst);
+    if cancelled.is_null() {
+        return;
+    }
+    // only the async-signal-safe calls are allowed here
+    if unsafe { &*cancelled }.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+impl CtrlCGuard {
+    #[cfg(unix)]
+    pub(crate) fn install(cancelled: Arc<AtomicBool>) -> io::Result<Self> {
+        use std::sync::atomic::Ordering;
+        let previous_flag =
+            CANCELLED.swap(Arc::as_ptr(&cancelled) as *mut AtomicBool, Ordering::SeqCst);
+        let mut previous_action = unsafe { std::mem::zeroed::<libc::sigaction>() };
+        let result = unsafe {
+            let mut action = std::mem::zeroed::<libc::sigaction>();
+            action.sa_sigaction = on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGINT, &action, &mut previous_action)
+        };
+        if result != 0 {
+            CANCELLED.store(previous_flag, Ordering::SeqCst);
+            return Err(io::Error::last_os_error());
+        }
+        Ok(CtrlCGuard {
+            previous_action,
+            previous_flag,
+            _cancelled: cancelled,
+        })
+    }
+
+    /// Ctrl-C is left to the default handling, which terminates the process
+    #[cfg(not(unix))]
+    pub(crate) fn install(cancelled: Arc<AtomicBool>) -> io::Result<Self> {
+        Ok(CtrlCGuard {
+            _cancelled: cancelled,
+        })
+    }
+}
+
+impl Drop for CtrlCGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            unsafe { libc::sigaction(libc::SIGINT, &self.previous_action, std::ptr::null_mut()) };
+            CANCELLED.store(self.previous_flag, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    fn current_handler() -> libc::sighandler_t {
+        unsafe {
+            let mut action = std::mem::zeroed::<libc::sigaction>();
+            libc::sigaction(libc::SIGINT, std::ptr::null(), &mut action);
+            action.sa_sigaction
+        }
+    }
+
+    #[test]
+    fn test_ctrl_c_guard() {
+        //Arrange
+        let before = current_handler();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        //Act
+        let guard = CtrlCGuard::install(cancelled.clone()).unwrap();
+        unsafe { libc::raise(libc::SIGINT) };
+        drop(guard);
+        //Assert
+        assert!(cancelled.load(Ordering::SeqCst));
+        assert_eq!(current_handler(), before);
+    }
+}
@@ -1,2 +1,10 @@
+mod atomic_file;
 pub mod aws;
+mod client;
+mod error;
+mod interrupt;
+pub mod lock;
 pub mod saml;
+
+pub use client::{AgentStrategy, ChromeSettings, Client, LoginBuilder, RoleChooser};
+pub use error::{CancelReason, Error, IdpError, Result};
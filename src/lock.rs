@@ -0,0 +1,117 @@
+//! Advisory locks among awsaml processes.
+use fs4::fs_std::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+
+/// An exclusive or shared lock on the sidecar `<path>.lock` of a file,
+/// which is released when dropped.
+/// It is advisory, so it only keeps out the processes taking the same lock.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+    exclusive: bool,
+}
+
+impl FileLock {
+    /// Wait until the lock of `path` is acquired
+    pub fn acquire(path: impl AsRef<Path>) -> Result<Self> {
+        let lock = Self::open(path.as_ref(), true)?;
+        lock.file.lock_exclusive()?;
+        log::debug!("Locked {}", lock.path.display());
+        Ok(lock)
+    }
+
+    /// Wait until the shared lock of `path` is acquired,
+    /// which keeps out the exclusive lock but not the other shared ones
+    pub fn acquire_shared(path: impl AsRef<Path>) -> Result<Self> {
+        let lock = Self::open(path.as_ref(), false)?;
+        lock.file.lock_shared()?;
+        log::debug!("Locked {} shared", lock.path.display());
+        Ok(lock)
+    }
+
+    /// Acquire the lock of `path` without waiting,
+    /// `None` if another process holds it.
+    pub fn try_acquire(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let lock = Self::open(path.as_ref(), true)?;
+        if lock.file.try_lock_exclusive()? {
+            log::debug!("Locked {}", lock.path.display());
+            Ok(Some(lock))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Whether other processes are kept out, which writing the file requires
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    fn open(path: &Path, exclusive: bool) -> Result<Self> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let path = PathBuf::from(lock_path);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        // the content is never read, so the file is left for the next process
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        Ok(FileLock {
+            file,
+            path,
+            exclusive,
+        })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        log::debug!("Unlocked {}", self.path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_try_acquire() {
+        //Arrange
+        let path = std::env::temp_dir().join(format!("awsaml-{}", Uuid::new_v4()));
+        let lock = FileLock::try_acquire(&path).unwrap();
+        //Act
+        let contended = FileLock::try_acquire(&path).unwrap();
+        drop(lock);
+        let released = FileLock::try_acquire(&path).unwrap();
+        //Assert
+        assert!(contended.is_none());
+        assert!(released.is_some());
+        let _ = fs::remove_file(&released.unwrap().path);
+    }
+
+    #[test]
+    fn test_acquire_shared() {
+        //Arrange
+        let path = std::env::temp_dir().join(format!("awsaml-{}", Uuid::new_v4()));
+        let shared = FileLock::acquire_shared(&path).unwrap();
+        //Act
+        let another_shared = FileLock::acquire_shared(&path).unwrap();
+        let exclusive = FileLock::try_acquire(&path).unwrap();
+        //Assert
+        assert!(!shared.is_exclusive());
+        assert!(exclusive.is_none());
+        drop(shared);
+        drop(another_shared);
+        let exclusive = FileLock::try_acquire(&path).unwrap().unwrap();
+        assert!(exclusive.is_exclusive());
+        let _ = fs::remove_file(&exclusive.path);
+    }
+}
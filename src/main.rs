@@ -1,16 +1,76 @@
 mod cmd;
 
 use anyhow::Result;
+use awsaml::{CancelReason, Error};
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> ExitCode {
     env_logger::init();
     let args = CommandArgs::parse();
     log::debug!("{:?}", args);
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+fn run(args: CommandArgs) -> Result<()> {
     match args.subcommand {
-        Subcommands::Configure => cmd::configure(&args.profile),
-        Subcommands::Login { role_name } => cmd::login(&args.profile, role_name),
+        Subcommands::Configure(configure_args) => cmd::configure(&args.profile, configure_args),
+        Subcommands::Login {
+            role_name,
+            manual,
+            saml_response,
+            debug_bundle,
+        } => cmd::login(
+            &args.profile,
+            role_name,
+            manual,
+            saml_response,
+            debug_bundle,
+        ),
+        Subcommands::Whoami {
+            all,
+            verify,
+            endpoint_url,
+            json,
+        } => cmd::whoami(&args.profile, all, verify, endpoint_url, json),
+        Subcommands::PromptSegment {
+            shell,
+            warn_minutes,
+            init,
+        } => cmd::prompt_segment(&args.profile, shell, warn_minutes, init),
+        Subcommands::Logout {
+            all,
+            wipe_cookies,
+            idp,
+        } => cmd::logout(&args.profile, all, wipe_cookies, idp),
+    }
+}
+
+/// The exit code for each kind of the library error, so that scripts can tell them apart
+fn exit_code(e: &anyhow::Error) -> u8 {
+    match e.downcast_ref::<Error>() {
+        Some(Error::Config(_)) => 2,
+        Some(Error::Idp(_)) => 3,
+        Some(Error::Browser(_)) => 4,
+        Some(Error::SamlDecode(_)) => 5,
+        Some(Error::SamlValidation(_)) => 6,
+        Some(Error::Sts(_)) => 7,
+        Some(Error::CredentialsStore(_)) => 8,
+        // same as timeout(1)
+        Some(Error::Timeout(_)) => 124,
+        // same as the shell on SIGINT
+        Some(Error::Cancelled(CancelReason::Interrupted)) => 130,
+        Some(Error::Cancelled(CancelReason::BrowserClosed)) => 10,
+        Some(Error::Io(_)) | None => 1,
+        // the errors added to the library later
+        Some(_) => 1,
     }
 }
 
@@ -19,19 +79,73 @@ async fn main() -> Result<()> {
 struct CommandArgs {
     #[command(subcommand)]
     subcommand: Subcommands,
-    /// AWS profile
-    #[arg(short, long, default_value = "default")]
+    /// AWS profile, AWS_PROFILE if not given as the AWS CLI does
+    #[arg(short, long, env = "AWS_PROFILE", default_value = "default")]
     profile: String,
 }
 
 #[derive(Debug, Subcommand)]
 enum Subcommands {
-    /// Configure the initial settings
-    Configure,
+    /// Configure the initial settings.
+    /// AWSAML_<KEY> environment variables, e.g. AWSAML_ENTRA_ID_TENANT, AWSAML_SESSION and
+    /// AWSAML_PARTITION, give the missing values here
+    /// and override the saved ones in the other commands
+    Configure(cmd::config::ConfigureArgs),
     /// Login with SAML SSO, then assume an AWS IAM role
     Login {
         /// AWS IAM role name
         #[arg(short, long)]
         role_name: Option<String>,
+        /// Print the login URL and paste the SAMLResponse instead of launching Chrome
+        #[arg(long)]
+        manual: bool,
+        /// Read the SAMLResponse from a file ("-" for stdin) instead of launching Chrome
+        #[arg(long, value_name = "FILE")]
+        saml_response: Option<PathBuf>,
+        /// Save a screenshot, the page and the redacted SAML messages into DIR when the login fails
+        #[arg(long, value_name = "DIR")]
+        debug_bundle: Option<PathBuf>,
+    },
+    /// Show the sessions saved by awsaml
+    #[command(alias = "status")]
+    Whoami {
+        /// Show all the profiles logged in with awsaml
+        #[arg(short, long)]
+        all: bool,
+        /// Check the credentials with STS GetCallerIdentity
+        #[arg(long)]
+        verify: bool,
+        /// Call this STS endpoint to verify, e.g. a local mock
+        #[arg(long, value_name = "URL", requires = "verify")]
+        endpoint_url: Option<String>,
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove the credentials and the assertions saved by awsaml
+    Logout {
+        /// Log out of all the profiles
+        #[arg(short, long)]
+        all: bool,
+        /// Remove the cookies of the IdP session kept in the Chrome user data dir
+        #[arg(long)]
+        wipe_cookies: bool,
+        /// End the IdP session of the profile by the SAML single logout first,
+        /// with the NameID and the SessionIndex of its last assertion.
+        /// Entra ID answers only if the enterprise application has a Logout URL
+        #[arg(long, conflicts_with = "all")]
+        idp: bool,
+    },
+    /// Print the profile and the time until expiry for the shell prompt, from the local files only
+    PromptSegment {
+        /// The shell printing the prompt, which decides how the colours are escaped
+        #[arg(long, value_enum, default_value_t = cmd::segment::Shell::Plain)]
+        shell: cmd::segment::Shell,
+        /// Turn yellow when less than MINUTES remain
+        #[arg(long, value_name = "MINUTES", default_value_t = 15)]
+        warn_minutes: i64,
+        /// Print the snippet to put in the configuration of SHELL instead
+        #[arg(long, value_name = "SHELL", value_enum)]
+        init: Option<cmd::segment::Shell>,
     },
 }
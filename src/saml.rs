@@ -1,22 +1,34 @@
+pub mod cache;
 mod chrome;
+mod diagnostics;
 pub mod entra_id;
+mod logout;
+mod manual;
 mod req;
 mod res;
 
-use anyhow::Result;
+use async_trait::async_trait;
 use base64::prelude::{Engine, BASE64_STANDARD};
-pub use chrome::ChromeSamlAgent;
+pub use chrome::{
+    wipe_cookies, ChromeLaunchOptions, ChromeSamlAgent, DEFAULT_IDLE_BROWSER_TIMEOUT,
+    DEFAULT_LOGIN_TIMEOUT, DEFAULT_SILENT_LOGIN_TIMEOUT,
+};
+pub use diagnostics::{redact_xml, DebugBundle};
 use flate2::read::DeflateDecoder;
+pub use logout::{SamlLogoutRequest, SamlLogoutResponse};
+pub use manual::{ManualSamlAgent, SamlResponseSource};
 pub use req::SamlAuthRequest;
-pub use res::SamlResponse;
+pub use res::{SamlResponse, SamlRole, SessionTag};
 use std::{
     fmt::Debug,
     io::{Read, Write},
 };
 use url::Url;
 
+use crate::{Error, Result};
+
 // acquire the SAML assertion from the IdP
-pub trait SamlIdProvider: Debug {
+pub trait SamlIdProvider: Debug + Send + Sync {
     fn request_url(&self, saml_request: SamlAuthRequest) -> Url {
         let mut url = self.request_base();
         url.set_query(Some(&format!(
@@ -27,6 +39,44 @@ pub trait SamlIdProvider: Debug {
     }
 
     fn request_base(&self) -> Url;
+
+    /// The endpoint receiving the LogoutRequest of the single logout, if supported
+    fn logout_url(&self) -> Option<Url> {
+        None
+    }
+
+    /// CSS selector matching the form where the user enters the credentials.
+    /// The headless login gives up as soon as it is shown.
+    fn interactive_login_selector(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether an error response is a part of the normal login flow,
+    /// such as probes of the conditional access.
+    fn is_benign_error_response(&self, _url: &Url, _status: u32) -> bool {
+        false
+    }
+
+    /// A readable description of the error shown in the error page
+    fn describe_error_page(&self, _html: &str) -> Option<String> {
+        None
+    }
+}
+
+// send the SAML request to the IdP and bring back its SAML response
+#[async_trait]
+pub trait SamlAgent: Debug + Send {
+    async fn saml_request_to_idp(&mut self, saml_req: SamlAuthRequest) -> Result<SamlResponse>;
+
+    /// Send the LogoutRequest to the IdP and bring back its LogoutResponse
+    async fn logout_request_to_idp(
+        &mut self,
+        _logout_req: SamlLogoutRequest,
+    ) -> Result<SamlLogoutResponse> {
+        Err(Error::Config(
+            "The agent does not support the single logout".to_string(),
+        ))
+    }
 }
 
 type Base64EncodedXMLString = String;
@@ -44,12 +94,49 @@ impl EncodedSAML {
         EncodedSAML(encoded.to_string())
     }
 
+    /// Construct an instance from a value entered by the user.
+    /// It accepts both the raw base64 string and the URL-encoded one
+    /// (as seen in the form data of the browser dev tools),
+    /// optionally prefixed with `SAMLResponse=`.
+    pub fn from_input(input: &str) -> Result<Self> {
+        let input = input.trim();
+        let input = input.strip_prefix("SAMLResponse=").unwrap_or(input);
+        // pasted values are often wrapped into multiple lines
+        let input: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+        if input.is_empty() {
+            return Err(Error::SamlDecode("SAMLResponse is empty".to_string()));
+        }
+
+        // '%' never appears in base64, so it must be URL-encoded
+        let base64 = if input.contains('%') {
+            urlencoding::decode(&input)
+                .map_err(Error::saml_decode)?
+                .into_owned()
+        } else {
+            input
+        };
+        Ok(EncodedSAML(base64))
+    }
+
     /// Convert the instance to a raw XML string
     fn to_raw_string(&self) -> Result<RawXMLString> {
-        let decoded = urlencoding::decode(&self.0)?;
-        let bytes = BASE64_STANDARD.decode(decoded.into_owned())?;
-        let raw_xml = Self::inflate(bytes);
-        Ok(raw_xml)
+        let bytes = BASE64_STANDARD
+            .decode(self.to_base64()?)
+            .map_err(Error::saml_decode)?;
+        // SAMLRequest of HTTP-Redirect binding is deflated,
+        // but SAMLResponse of HTTP-POST binding is not.
+        match String::from_utf8(bytes) {
+            Ok(xml) if xml.trim_start().starts_with('<') => Ok(xml),
+            Ok(xml) => Self::inflate(xml.into_bytes()),
+            Err(e) => Self::inflate(e.into_bytes()),
+        }
+    }
+
+    /// Convert the instance to a base64 string without URL-encoding
+    pub fn to_base64(&self) -> Result<Base64EncodedXMLString> {
+        Ok(urlencoding::decode(&self.0)
+            .map_err(Error::saml_decode)?
+            .into_owned())
     }
 
     fn deflate(str: String) -> Vec<u8> {
@@ -59,11 +146,13 @@ impl EncodedSAML {
         deflater.finish().unwrap()
     }
 
-    fn inflate(bytes: Vec<u8>) -> String {
+    fn inflate(bytes: Vec<u8>) -> Result<String> {
         let mut inflater = DeflateDecoder::new(bytes.as_slice());
         let mut str = String::new();
-        inflater.read_to_string(&mut str).unwrap();
-        str
+        inflater
+            .read_to_string(&mut str)
+            .map_err(Error::saml_decode)?;
+        Ok(str)
     }
 }
 
@@ -101,4 +190,37 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), raw_string);
     }
+
+    #[test]
+    fn test_from_input_url_encoded() {
+        //Arrange
+        let input = format!("SAMLResponse={}\n", ENCODED_SAML_REQUEST);
+        //Act
+        let result = EncodedSAML::from_input(&input).unwrap();
+        //Assert
+        assert!(!result.0.contains('%'));
+        assert!(result
+            .to_raw_string()
+            .unwrap()
+            .starts_with("<samlp:AuthnRequest"));
+    }
+
+    #[test]
+    fn test_from_input_raw_base64() {
+        //Arrange
+        let xml = r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"/>"#;
+        let encoded = BASE64_STANDARD.encode(xml);
+        // wrapped into multiple lines as pasted from a terminal
+        let input = format!("{}\n{}\n", &encoded[..10], &encoded[10..]);
+        //Act
+        let result = EncodedSAML::from_input(&input).unwrap();
+        //Assert
+        assert_eq!(result.to_base64().unwrap(), encoded);
+        assert_eq!(result.to_raw_string().unwrap(), xml);
+    }
+
+    #[test]
+    fn test_from_input_empty() {
+        assert!(EncodedSAML::from_input(" \n").is_err());
+    }
 }
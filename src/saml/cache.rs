@@ -0,0 +1,58 @@
+//! The assertion of the last login of each profile,
+//! kept to show the session of the profile and to log out of the IdP.
+use std::fs;
+use std::path::PathBuf;
+
+use super::{EncodedSAML, SamlResponse};
+use crate::aws;
+use crate::{atomic_file, Result};
+
+/// Save the assertion of the profile, readable only by the user
+pub fn save(profile: &str, saml_response: &SamlResponse) -> Result<()> {
+    let path = path_of(profile);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    atomic_file::write(&path, saml_response.to_base64()?.as_bytes())?;
+    log::debug!("Saved the assertion to {}", path.display());
+    Ok(())
+}
+
+/// The assertion of the last login of the profile, `None` if it has not logged in
+pub fn load(profile: &str) -> Result<Option<SamlResponse>> {
+    let path = path_of(profile);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let encoded = fs::read_to_string(&path)?;
+    SamlResponse::from_encoded(EncodedSAML::from_input(&encoded)?).map(Some)
+}
+
+/// Remove the assertion of the profile, `false` if there is none
+pub fn remove(profile: &str) -> Result<bool> {
+    let path = path_of(profile);
+    if !path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(&path)?;
+    Ok(true)
+}
+
+/// Remove the assertions of all the profiles, and returns how many are removed
+pub fn remove_all() -> Result<usize> {
+    let dir = dir();
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let count = fs::read_dir(&dir)?.count();
+    fs::remove_dir_all(&dir)?;
+    Ok(count)
+}
+
+fn dir() -> PathBuf {
+    aws::state_dir().join("assertions")
+}
+
+fn path_of(profile: &str) -> PathBuf {
+    dir().join(format!("{}.saml", aws::file_name_of(profile)))
+}
@@ -1,105 +1,485 @@
 //! Chrome SAML support.
-use super::{EncodedSAML, Result, SamlAuthRequest, SamlIdProvider, SamlResponse};
-use anyhow::anyhow;
-use headless_chrome::protocol::cdp::types::Event;
-use headless_chrome::protocol::cdp::Network::{self, Request};
+use super::{
+    DebugBundle, EncodedSAML, SamlAgent, SamlAuthRequest, SamlIdProvider, SamlLogoutRequest,
+    SamlLogoutResponse, SamlResponse,
+};
+use crate::interrupt::CtrlCGuard;
+use crate::{CancelReason, Error, IdpError, Result};
+use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use headless_chrome::browser::tab::RequestPausedDecision;
+use headless_chrome::browser::transport::{SessionId, Transport};
+use headless_chrome::protocol::cdp::Fetch::{
+    events::RequestPausedEvent, FulfillRequest, HeaderEntry, RequestId, RequestPattern,
+    RequestStage,
+};
+use headless_chrome::protocol::cdp::Network::{
+    self, GetRequestPostData, PostDataEntry, Request, ResourceType,
+};
+use headless_chrome::protocol::cdp::Page::CaptureScreenshotFormatOption;
 use headless_chrome::{Browser, LaunchOptions, Tab};
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
 use std::sync::{
-    mpsc::{channel, Receiver},
+    atomic::{AtomicBool, Ordering},
+    mpsc::{channel, Receiver, RecvTimeoutError},
     Arc,
 };
+use std::time::{Duration, Instant};
 use url::{form_urlencoded, Url};
 
-#[derive(Debug)]
+/// The page shown in place of the SP after the SAML response is captured
+const LOGIN_COMPLETED_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>awsaml</title></head>
+<body>
+<h1>Login completed</h1>
+<p>You can close this window and return to the terminal.</p>
+</body>
+</html>"#;
+/// The page shown in place of the SP when the SAML response cannot be captured
+const LOGIN_FAILED_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>awsaml</title></head>
+<body>
+<h1>Login failed</h1>
+<p>See the terminal for details. You can close this window.</p>
+</body>
+</html>"#;
+/// The page shown in place of the SP after the LogoutResponse is captured
+const LOGOUT_COMPLETED_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>awsaml</title></head>
+<body>
+<h1>Logout completed</h1>
+<p>You can close this window and return to the terminal.</p>
+</body>
+</html>"#;
+/// The page shown in place of the SP when the LogoutResponse cannot be captured
+const LOGOUT_FAILED_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>awsaml</title></head>
+<body>
+<h1>Logout failed</h1>
+<p>See the terminal for details. You can close this window.</p>
+</body>
+</html>"#;
+/// The redirect carrying the LogoutResponse to the SP, by the HTTP-Redirect binding
+const LOGOUT_RESPONSE_PATTERN: &str = "*SAMLResponse=*";
+
+/// How long the user is given to complete the login by default
+pub const DEFAULT_LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long the silent (headless) login is given by default before falling back to a window
+pub const DEFAULT_SILENT_LOGIN_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long the IdP is given to redirect the LogoutResponse.
+/// It is short, as the IdP may never redirect it, e.g. when the app has no logout URL.
+const LOGOUT_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long the browser may stay idle by default.
+/// The login page makes no events while the user is away, so it is as long as the login.
+pub const DEFAULT_IDLE_BROWSER_TIMEOUT: Duration = DEFAULT_LOGIN_TIMEOUT;
+/// How often the agent checks the browser and the cancellation while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the running browser is given to answer `/json/version`
+const DEVTOOLS_HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+/// The cookie store in each profile of a user data dir, moved under `Network` by Chrome 96
+const COOKIE_FILES: [&str; 4] = [
+    "Cookies",
+    "Cookies-journal",
+    "Network/Cookies",
+    "Network/Cookies-journal",
+];
+/// The symlink to `<hostname>-<pid>` of the Chrome running with a user data dir on Linux and macOS.
+/// It is left behind when Chrome is killed, as headless_chrome does on exit.
+const SINGLETON_LOCK: &str = "SingletonLock";
+/// The file held open by the Chrome running with a user data dir on Windows,
+/// which is deleted when Chrome exits, even when killed.
+const WINDOWS_LOCK_FILE: &str = "lockfile";
+
+/// How to launch the browser
+#[derive(Debug, Clone)]
+pub struct ChromeLaunchOptions {
+    /// The executable of Chrome, Chromium, Edge or Brave.
+    /// It is searched in the default locations if `None`.
+    pub path: Option<PathBuf>,
+    /// Extra command line arguments, e.g. `--proxy-server=...`, `--lang=ja` or `--window-size=800,600`
+    pub args: Vec<String>,
+    /// Whether to launch the browser with the sandbox
+    pub sandbox: bool,
+    /// How long the browser may stay idle before the connection is closed.
+    /// It is extended to the login timeout if shorter,
+    /// since the connection closed while the user is still on the page looks like a closed browser.
+    pub idle_timeout: Duration,
+}
+
+impl Default for ChromeLaunchOptions {
+    fn default() -> Self {
+        ChromeLaunchOptions {
+            path: None,
+            args: Vec::new(),
+            sandbox: true,
+            idle_timeout: DEFAULT_IDLE_BROWSER_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 /// An agent that performs SAML authentication by manipulating Headless Chrome
 pub struct ChromeSamlAgent {
-    idp: Box<dyn SamlIdProvider>,
+    idp: Arc<dyn SamlIdProvider>,
     sp_callback_url: String,
     user_data_dir: PathBuf,
+    launch_options: ChromeLaunchOptions,
+    recv_timeout: Duration,
+    silent_timeout: Option<Duration>,
+    remote_debugging_url: Option<String>,
+    debug_bundle: Option<Arc<DebugBundle>>,
+    cancelled: Arc<AtomicBool>,
+    cancel_on_ctrl_c: bool,
 }
 
 impl ChromeSamlAgent {
     pub fn new(idp: Box<dyn SamlIdProvider>, callback_url: Url, user_data_dir: PathBuf) -> Self {
         ChromeSamlAgent {
-            idp,
+            idp: Arc::from(idp),
             sp_callback_url: callback_url.to_string(),
             user_data_dir,
+            launch_options: ChromeLaunchOptions::default(),
+            recv_timeout: DEFAULT_LOGIN_TIMEOUT,
+            silent_timeout: None,
+            remote_debugging_url: None,
+            debug_bundle: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            cancel_on_ctrl_c: false,
         }
     }
 
-    /// To acquire SAML assertion from IdP,
-    /// the agent will send a SAML request to IdP
-    /// with launching a browser tab.
-    pub fn saml_request_to_idp(&mut self, saml_req: SamlAuthRequest) -> Result<SamlResponse> {
-        let (_browser, tab, receiver) = self.launch_browser_tab()?;
+    /// Set the executable and the arguments to launch the browser
+    pub fn with_launch_options(mut self, launch_options: ChromeLaunchOptions) -> Self {
+        self.launch_options = launch_options;
+        self
+    }
+
+    /// Set how long to wait for the user to complete the login
+    pub fn with_timeout(mut self, recv_timeout: Duration) -> Self {
+        self.recv_timeout = recv_timeout;
+        self
+    }
+
+    /// Try a headless login first, relying on the IdP session kept in the user data dir.
+    /// If it does not reach the callback within `silent_timeout`,
+    /// or the IdP asks the user to sign in, the agent falls back to a visible window.
+    pub fn with_silent_login(mut self, silent_timeout: Duration) -> Self {
+        self.silent_timeout = Some(silent_timeout);
+        self
+    }
+
+    /// Open the login tab in an already running Chrome instead of launching a new one,
+    /// so that the user's everyday browser profile is used.
+    /// `remote_debugging_url` is either the DevTools WebSocket URL (`ws://...`),
+    /// the HTTP endpoint (`http://127.0.0.1:9222`) or just the `--remote-debugging-port`.
+    pub fn with_remote_debugging(mut self, remote_debugging_url: String) -> Self {
+        self.remote_debugging_url = Some(remote_debugging_url);
+        self
+    }
+
+    /// Save the diagnostics into `dir` when the login fails
+    pub fn with_debug_bundle(mut self, dir: PathBuf) -> Self {
+        self.debug_bundle = Some(Arc::new(DebugBundle::new(dir)));
+        self
+    }
+
+    /// A flag to cancel the login from another thread (e.g. a Ctrl-C handler).
+    /// The agent gives up waiting as soon as it is set to `true`.
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    /// Share the cancel flag created beforehand, instead of the own one
+    pub fn with_cancel_handle(mut self, cancelled: Arc<AtomicBool>) -> Self {
+        self.cancelled = cancelled;
+        self
+    }
+
+    /// Set the cancel flag on Ctrl-C while waiting for the browser, and exit on the second one.
+    /// SIGINT is handled as before once the wait is over.
+    pub fn with_cancel_on_ctrl_c(mut self) -> Self {
+        self.cancel_on_ctrl_c = true;
+        self
+    }
+
+    /// Handle Ctrl-C until the guard is dropped, if enabled
+    fn guard_ctrl_c(&self) -> Result<Option<CtrlCGuard>> {
+        if !self.cancel_on_ctrl_c {
+            return Ok(None);
+        }
+        Ok(Some(CtrlCGuard::install(self.cancelled.clone())?))
+    }
+
+    /// Try the silent login if enabled, then the login in a window
+    fn login(&mut self, saml_req: SamlAuthRequest) -> Result<SamlResponse> {
+        let _ctrl_c = self.guard_ctrl_c()?;
+        if let Some(debug_bundle) = &self.debug_bundle {
+            debug_bundle.record_authn_request(&saml_req.to_string());
+        }
         let url = self.idp.request_url(saml_req).to_string();
-        log::debug!("Navigating to: {}", &url);
-        tab.navigate_to(&url)?;
+        // an already running browser cannot be switched to the headless mode
+        let silent_timeout = self
+            .silent_timeout
+            .filter(|_| self.remote_debugging_url.is_none());
+        if let Some(silent_timeout) = silent_timeout {
+            match self.login_in_browser(&url, true, silent_timeout) {
+                Ok(saml_res) => return Ok(saml_res),
+                Err(e @ Error::Cancelled(CancelReason::Interrupted)) => return Err(e),
+                Err(e) => log::info!("Silent login did not complete: {}", e),
+            }
+        }
+        let timeout = self.recv_timeout;
+        self.login_in_browser(&url, false, timeout)
+    }
+
+    /// Open the IdP login page in a new browser,
+    /// and wait for the SAML response posted to the SP callback.
+    fn login_in_browser(
+        &mut self,
+        url: &str,
+        headless: bool,
+        timeout: Duration,
+    ) -> Result<SamlResponse> {
+        let (browser, tab, receiver) = self.launch_browser_tab(headless)?;
+        log::debug!("Navigating to: {}", url);
+        tab.navigate_to(url).map_err(Error::browser)?;
         log::debug!("navigated");
-        receiver.recv()?
+        let result = self.wait_for_saml_response(&browser, &tab, receiver, timeout, headless);
+        if let (Err(e), Some(debug_bundle)) = (&result, &self.debug_bundle) {
+            Self::save_debug_bundle(debug_bundle, &tab, e, headless);
+        }
+        if self.remote_debugging_url.is_some() && result.is_err() {
+            // leave the user's browser as it was, except for our tab,
+            // which shows the login completed page on success
+            let _ = tab.close(false);
+        }
+        result
+    }
+
+    /// Save the diagnostics with the current state of the tab.
+    /// It is best effort, since the browser may be already gone.
+    /// The silent attempt is saved apart, as it shows why the window was needed.
+    fn save_debug_bundle(debug_bundle: &DebugBundle, tab: &Tab, error: &Error, headless: bool) {
+        let screenshot = tab
+            .capture_screenshot(CaptureScreenshotFormatOption::Png, None, None, true)
+            .ok();
+        let page_html = tab.get_content().ok();
+        let attempt = if headless { "silent" } else { "window" };
+        match debug_bundle.save(attempt, error, screenshot, page_html) {
+            Ok(dir) => eprintln!("Saved the diagnostics to {}", dir.display()),
+            Err(e) => log::error!("Failed to save the diagnostics: {}", e),
+        }
+    }
+
+    /// Wait for the SAML response (or the LogoutResponse),
+    /// watching the timeout, the cancellation and the browser window.
+    /// In the headless mode, it also gives up when the IdP shows an interactive login page,
+    /// because nobody can fill it in.
+    fn wait_for_saml_response<T>(
+        &self,
+        browser: &Browser,
+        tab: &Tab,
+        receiver: Receiver<Result<T>>,
+        timeout: Duration,
+        headless: bool,
+    ) -> Result<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(result) => return result,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(CancelReason::BrowserClosed.into())
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if self.cancelled.load(Ordering::SeqCst) {
+                return Err(CancelReason::Interrupted.into());
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout(timeout));
+            }
+            if !Self::is_tab_open(browser, tab) {
+                return Err(CancelReason::BrowserClosed.into());
+            }
+            if headless && self.is_interactive_login_page(tab) {
+                return Err(IdpError::InteractionRequired.into());
+            }
+        }
+    }
+
+    /// Whether the tab shows a page where the user has to enter something
+    fn is_interactive_login_page(&self, tab: &Tab) -> bool {
+        self.idp
+            .interactive_login_selector()
+            .is_some_and(|selector| tab.find_element(selector).is_ok())
+    }
+
+    /// Whether the user still keeps the browser and the login tab open
+    fn is_tab_open(browser: &Browser, tab: &Tab) -> bool {
+        let tab_exists = browser
+            .get_tabs()
+            .lock()
+            .map(|tabs| {
+                tabs.iter()
+                    .any(|t| t.get_target_id() == tab.get_target_id())
+            })
+            .unwrap_or(false);
+        tab_exists && browser.get_version().is_ok()
+    }
+
+    /// Launch a browser with the user data dir, or connect to the running one
+    fn launch_browser(&self, headless: bool) -> Result<Browser> {
+        let browser = match &self.remote_debugging_url {
+            Some(remote_debugging_url) => {
+                let ws_url = debugger_ws_url(remote_debugging_url)?;
+                log::debug!("Connecting to: {}", &ws_url);
+                Browser::connect_with_timeout(ws_url, self.recv_timeout).map_err(Error::browser)?
+            }
+            None => Browser::new(LaunchOptions {
+                headless,
+                user_data_dir: Some(self.user_data_dir.clone()),
+                path: self.launch_options.path.clone(),
+                args: self.launch_options.args.iter().map(OsStr::new).collect(),
+                sandbox: self.launch_options.sandbox,
+                idle_browser_timeout: self.launch_options.idle_timeout.max(self.recv_timeout),
+                ..Default::default()
+            })
+            .map_err(Error::browser)?,
+        };
+        Ok(browser)
     }
 
     /// Launch a browser tab,
     /// and set event listener to capture the callback request
     fn launch_browser_tab(
         &mut self,
+        headless: bool,
     ) -> Result<(Browser, Arc<Tab>, Receiver<Result<SamlResponse>>)> {
-        let browser = Browser::new(LaunchOptions {
-            headless: false,
-            user_data_dir: Some(self.user_data_dir.clone()),
-            ..Default::default()
-        })?;
-        let tab = browser.new_tab()?;
-
-        let _ = tab.call_method(Network::Enable {
-            max_total_buffer_size: Some(100_000_000),
-            max_resource_buffer_size: Some(100_000_000),
-            max_post_data_size: Some(100_000_000),
-        })?;
+        let browser = self.launch_browser(headless)?;
+        let tab = browser.new_tab().map_err(Error::browser)?;
+
+        let _ = tab
+            .call_method(Network::Enable {
+                max_total_buffer_size: Some(100_000_000),
+                max_resource_buffer_size: Some(100_000_000),
+                max_post_data_size: Some(100_000_000),
+            })
+            .map_err(Error::browser)?;
 
         let (sender, receiver) = channel::<Result<SamlResponse>>();
         let sender = Arc::new(sender);
 
-        // add event listener to capture the callback request
+        // intercept the callback request to capture the SAMLResponse,
+        // and answer it locally so that it never reaches the SP
         {
             let callback_url = self.sp_callback_url.to_string();
             let sender = sender.clone();
-            tab.add_event_listener(Arc::new(move |event: &Event| match event {
-                Event::NetworkRequestWillBeSent(e) => {
-                    if e.params.request.url == callback_url && e.params.request.method == "POST" {
-                        let r = Self::capture_callback_request(&e.params.request);
-                        sender.send(r).expect("Failed to send to receiver");
+            let debug_bundle = self.debug_bundle.clone();
+            tab.enable_fetch(
+                Some(&[RequestPattern {
+                    url_pattern: Some(callback_url.clone()),
+                    resource_Type: None,
+                    request_stage: Some(RequestStage::Request),
+                }]),
+                None,
+            )
+            .map_err(Error::browser)?;
+            tab.enable_request_interception(Arc::new(
+                move |transport: Arc<Transport>,
+                      session_id: SessionId,
+                      event: RequestPausedEvent| {
+                    let request = &event.params.request;
+                    if request.url != callback_url || request.method != "POST" {
+                        return RequestPausedDecision::Continue(None);
                     }
-                }
-                _ => {}
-            }))?;
-            log::debug!("event listener added");
+                    // Chrome does not inline large bodies, which must be fetched separately
+                    let fetch_post_data = || {
+                        let network_id = event
+                            .params
+                            .network_id
+                            .clone()
+                            .ok_or(Error::SamlValidation("No post data entries".to_string()))?;
+                        let post_data = transport
+                            .call_method_on_target(
+                                session_id.clone(),
+                                GetRequestPostData {
+                                    request_id: network_id,
+                                },
+                            )
+                            .map_err(Error::browser)?;
+                        Ok(post_data.post_data)
+                    };
+                    let r = Self::capture_callback_request(
+                        request,
+                        fetch_post_data,
+                        debug_bundle.as_deref(),
+                    );
+                    let page = if r.is_ok() {
+                        LOGIN_COMPLETED_PAGE
+                    } else {
+                        LOGIN_FAILED_PAGE
+                    };
+                    let _ = sender.send(r);
+                    RequestPausedDecision::Fulfill(fulfill_with_page(event.params.request_id, page))
+                },
+            ))
+            .map_err(Error::browser)?;
+            log::debug!("request interception enabled");
         }
         // add response handler to handle error response
         {
             let sender = sender.clone();
+            let debug_bundle = self.debug_bundle.clone();
+            let idp = self.idp.clone();
+            let callback_url = self.sp_callback_url.to_string();
             tab.register_response_handling(
                 "document",
-                Box::new(move |params, fetch_body| match params.response.status {
-                    401 => {
-                        log::error!("401: {:?}", params.response.url);
+                Box::new(move |params, fetch_body| {
+                    if let (Some(debug_bundle), ResourceType::Document) =
+                        (&debug_bundle, &params.Type)
+                    {
+                        debug_bundle
+                            .record_navigation(params.response.status, &params.response.url);
                     }
-                    400.. => {
-                        log::error!("Received response is error: {:?}", params.response);
-                        sender
-                            .send(Err(anyhow!(
-                                "Response error {}, {}",
+                    if !is_login_error(
+                        idp.as_ref(),
+                        &callback_url,
+                        &params.Type,
+                        params.response.status,
+                        &params.response.url,
+                    ) {
+                        if params.response.status >= 400 {
+                            log::debug!(
+                                "Ignored error response: {} {}",
                                 params.response.status,
                                 params.response.url
-                            )))
-                            .expect("Failed to send to receiver");
+                            );
+                        }
+                        return;
                     }
-                    _ => {
-                        //                        log::info!("{}: {}", params.response.status, params.response.url)
+                    log::error!("Received response is error: {:?}", params.response);
+                    let message = fetch_body()
+                        .ok()
+                        .map(|body| decode_body(body.body, body.base_64_encoded))
+                        .and_then(|body| idp.describe_error_page(&body));
+                    // the receiver is gone once the login has finished
+                    let _ = sender.send(Err(IdpError::ErrorPage {
+                        status: params.response.status,
+                        url: params.response.url.clone(),
+                        message,
                     }
+                    .into()));
                 }),
-            )?;
+            )
+            .map_err(Error::browser)?;
             log::debug!("response handler added");
         }
         Ok((browser, tab, receiver))
@@ -108,24 +488,575 @@ impl ChromeSamlAgent {
     /// capture the callback request from IdP to SP
     /// and then extract the SAMLResponse.
     /// Note: The name is "response" but it's actually a redirect request from IdP to SP.
-    fn capture_callback_request(callback_request: &Request) -> Result<SamlResponse> {
-        let post_entries = callback_request
-            .post_data_entries
-            .clone()
-            .ok_or(anyhow!("No post data entries"))?;
-
-        // concatenate all flagmented post data entries into a single string
-        let concatinated = post_entries
-            .iter()
-            .filter_map(|entry| entry.bytes.to_owned())
-            .collect::<Vec<String>>()
-            .join("");
-
-        let saml_response = form_urlencoded::parse(concatinated.as_bytes())
+    fn capture_callback_request(
+        callback_request: &Request,
+        fetch_post_data: impl Fn() -> Result<String>,
+        debug_bundle: Option<&DebugBundle>,
+    ) -> Result<SamlResponse> {
+        let post_data = match Self::inline_post_data(callback_request)? {
+            Some(post_data) => post_data,
+            None => fetch_post_data()?,
+        };
+        Self::extract_saml_response(&post_data, debug_bundle)
+    }
+
+    /// The request body inlined in the request, if any
+    fn inline_post_data(request: &Request) -> Result<Option<String>> {
+        match &request.post_data_entries {
+            Some(entries) if !entries.is_empty() => concat_post_data_entries(entries).map(Some),
+            _ => Ok(request.post_data.clone()),
+        }
+    }
+
+    /// extract the SAMLResponse from the form data posted to the SP.
+    /// It is recorded into the debug bundle before the validation,
+    /// so that the response which fails is there too.
+    fn extract_saml_response(
+        post_data: &str,
+        debug_bundle: Option<&DebugBundle>,
+    ) -> Result<SamlResponse> {
+        let saml_response = form_urlencoded::parse(post_data.as_bytes())
             .find(|(key, _)| key == "SAMLResponse")
             .map(|(_, value)| value.to_string())
-            .ok_or(anyhow!("No SAMLResponse found"))?;
+            .ok_or(Error::SamlValidation("No SAMLResponse found".to_string()))?;
+
+        let encoded = EncodedSAML(saml_response);
+        if let Some(debug_bundle) = debug_bundle {
+            debug_bundle.record_saml_response(&encoded);
+        }
+        SamlResponse::from_encoded(encoded)
+    }
+
+    /// Send the LogoutRequest in a browser window, which carries the IdP session,
+    /// and wait for the LogoutResponse redirected to the SP.
+    /// The window lets the user pick the account if the IdP asks for it.
+    fn logout(&self, logout_req: SamlLogoutRequest) -> Result<SamlLogoutResponse> {
+        let _ctrl_c = self.guard_ctrl_c()?;
+        let browser = self.launch_browser(false)?;
+        let tab = browser.new_tab().map_err(Error::browser)?;
+
+        let (sender, receiver) = channel::<Result<SamlLogoutResponse>>();
+        // answer the redirect locally like the login callback, so that it never reaches the SP
+        tab.enable_fetch(
+            Some(&[RequestPattern {
+                url_pattern: Some(LOGOUT_RESPONSE_PATTERN.to_string()),
+                resource_Type: None,
+                request_stage: Some(RequestStage::Request),
+            }]),
+            None,
+        )
+        .map_err(Error::browser)?;
+        tab.enable_request_interception(Arc::new(
+            move |_: Arc<Transport>, _: SessionId, event: RequestPausedEvent| {
+                let r = Self::extract_logout_response(&event.params.request.url);
+                let page = if r.is_ok() {
+                    LOGOUT_COMPLETED_PAGE
+                } else {
+                    LOGOUT_FAILED_PAGE
+                };
+                let _ = sender.send(r);
+                RequestPausedDecision::Fulfill(fulfill_with_page(event.params.request_id, page))
+            },
+        ))
+        .map_err(Error::browser)?;
+
+        let url = logout_req.redirect_url();
+        log::debug!("Navigating to: {}", url);
+        tab.navigate_to(url.as_str()).map_err(Error::browser)?;
+        let result = self.wait_for_saml_response(
+            &browser,
+            &tab,
+            receiver,
+            LOGOUT_TIMEOUT.min(self.recv_timeout),
+            false,
+        );
+        if self.remote_debugging_url.is_some() && result.is_err() {
+            let _ = tab.close(false);
+        }
+        result
+    }
+
+    /// extract the SAMLResponse from the query of the redirect to the SP
+    fn extract_logout_response(url: &str) -> Result<SamlLogoutResponse> {
+        let url = Url::parse(url).map_err(Error::saml_decode)?;
+        let saml_response = url
+            .query_pairs()
+            .find(|(key, _)| key == "SAMLResponse")
+            .map(|(_, value)| value.to_string())
+            .ok_or(Error::SamlValidation("No SAMLResponse found".to_string()))?;
+
+        SamlLogoutResponse::from_encoded(EncodedSAML(saml_response))
+    }
+}
+
+/// Answer the paused request with a page of awsaml
+fn fulfill_with_page(request_id: RequestId, page: &str) -> FulfillRequest {
+    FulfillRequest {
+        request_id,
+        response_code: 200,
+        response_headers: Some(vec![HeaderEntry {
+            name: "Content-Type".to_string(),
+            value: "text/html; charset=utf-8".to_string(),
+        }]),
+        binary_response_headers: None,
+        body: Some(BASE64_STANDARD.encode(page)),
+        response_phrase: None,
+    }
+}
+
+/// Whether the response means the login has failed.
+/// Only the error documents from the IdP login domain or the SP callback count,
+/// except for those the IdP regards as a part of the normal flow.
+fn is_login_error(
+    idp: &dyn SamlIdProvider,
+    callback_url: &str,
+    resource_type: &ResourceType,
+    status: u32,
+    url: &str,
+) -> bool {
+    if status < 400 || *resource_type != ResourceType::Document {
+        return false;
+    }
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    if idp.is_benign_error_response(&url, status) {
+        return false;
+    }
+    url.host_str() == idp.request_base().host_str() || url.as_str().starts_with(callback_url)
+}
+
+fn decode_body(body: String, base64_encoded: bool) -> String {
+    if !base64_encoded {
+        return body;
+    }
+    match BASE64_STANDARD.decode(&body) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(_) => body,
+    }
+}
+
+/// concatenate all flagmented post data entries into a single string.
+/// The bytes of each entry are base64 encoded by the DevTools protocol,
+/// so an entry which cannot be decoded is an error rather than taken as it is.
+fn concat_post_data_entries(entries: &[PostDataEntry]) -> Result<String> {
+    let mut bytes = Vec::new();
+    for entry in entries.iter().filter_map(|entry| entry.bytes.as_deref()) {
+        let decoded = BASE64_STANDARD
+            .decode(entry)
+            .map_err(|e| Error::SamlDecode(format!("Invalid post data entry: {}", e)))?;
+        bytes.extend(decoded);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[async_trait]
+impl SamlAgent for ChromeSamlAgent {
+    /// To acquire SAML assertion from IdP,
+    /// the agent will send a SAML request to IdP
+    /// with launching a browser tab.
+    async fn saml_request_to_idp(&mut self, saml_req: SamlAuthRequest) -> Result<SamlResponse> {
+        // the browser is driven by blocking calls, so keep them off the executor threads.
+        // the clone shares the cancel handle and the debug bundle with this agent.
+        let mut agent = self.clone();
+        tokio::task::spawn_blocking(move || agent.login(saml_req))
+            .await
+            .map_err(Error::browser)?
+    }
+
+    /// The LogoutRequest is sent with the same browser and user data dir as the login,
+    /// where the IdP keeps its session.
+    async fn logout_request_to_idp(
+        &mut self,
+        logout_req: SamlLogoutRequest,
+    ) -> Result<SamlLogoutResponse> {
+        let agent = self.clone();
+        tokio::task::spawn_blocking(move || agent.logout(logout_req))
+            .await
+            .map_err(Error::browser)?
+    }
+}
+
+/// Remove the cookies kept in the user data dir, including the session of the IdP,
+/// so that the next login asks the credentials again.
+/// The other data of the profiles are left, and so are the other files of the directory.
+///
+/// Returns the removed files. Fails if Chrome is running with the directory.
+pub fn wipe_cookies(user_data_dir: &Path) -> Result<Vec<PathBuf>> {
+    if is_chrome_running(user_data_dir) {
+        return Err(Error::Browser(format!(
+            "Chrome is running with {}, close it first",
+            user_data_dir.display()
+        )));
+    }
+    let mut removed = Vec::new();
+    let Ok(entries) = fs::read_dir(user_data_dir) else {
+        return Ok(removed);
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        // the profile directories, not anything else in a shared directory such as /tmp
+        if !(name == "Default" || name.starts_with("Profile ")) || !entry.path().is_dir() {
+            continue;
+        }
+        for file in COOKIE_FILES {
+            let path = entry.path().join(file);
+            if path.is_file() {
+                fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether a Chrome is running with the user data dir.
+/// As Chrome itself does, the `SingletonLock` only counts if its process is alive on this host.
+fn is_chrome_running(user_data_dir: &Path) -> bool {
+    if user_data_dir.join(WINDOWS_LOCK_FILE).exists() {
+        return true;
+    }
+    let Ok(target) = fs::read_link(user_data_dir.join(SINGLETON_LOCK)) else {
+        return false;
+    };
+    // the hostname may contain '-' as well
+    let target = target.to_string_lossy();
+    let Some((host, pid)) = target.rsplit_once('-') else {
+        return false;
+    };
+    match pid.parse() {
+        Ok(pid) => hostname().as_deref() == Some(host) && is_process_alive(pid),
+        Err(_) => false,
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[cfg(unix)]
+fn is_process_alive(pid: i32) -> bool {
+    // the signal 0 only checks the process, which may belong to another user (EPERM)
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Chrome does not create the `SingletonLock` on Windows
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn is_process_alive(_pid: i32) -> bool {
+    false
+}
+
+/// Resolve the DevTools WebSocket URL of a running browser.
+/// The browser publishes it at `/json/version` of its remote debugging port.
+fn debugger_ws_url(remote_debugging_url: &str) -> Result<String> {
+    let remote_debugging_url = remote_debugging_url.trim();
+    if remote_debugging_url.starts_with("ws://") || remote_debugging_url.starts_with("wss://") {
+        return Ok(remote_debugging_url.to_string());
+    }
+    let url = match remote_debugging_url.parse::<u16>() {
+        Ok(port) => Url::parse(&format!("http://127.0.0.1:{}", port)),
+        Err(_) => Url::parse(remote_debugging_url),
+    }
+    .map_err(|e| Error::Config(format!("{}: {}", remote_debugging_url, e)))?;
+    let host = url.host_str().ok_or(Error::Config(format!(
+        "No host in {}",
+        remote_debugging_url
+    )))?;
+    let port = url.port_or_known_default().ok_or(Error::Config(format!(
+        "No port in {}",
+        remote_debugging_url
+    )))?;
+
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(Error::Config(format!(
+            "{} is not resolved",
+            remote_debugging_url
+        )))?;
+    let mut stream = TcpStream::connect_timeout(&addr, DEVTOOLS_HTTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(DEVTOOLS_HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(DEVTOOLS_HTTP_TIMEOUT))?;
+    write!(
+        stream,
+        "GET /json/version HTTP/1.0\r\nHost: {}:{}\r\n\r\n",
+        host, port
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    parse_ws_url_from_version(&response).ok_or(Error::Browser(format!(
+        "No webSocketDebuggerUrl at {}",
+        remote_debugging_url
+    )))
+}
+
+/// Extract `webSocketDebuggerUrl` from the JSON body of the response of `/json/version`
+fn parse_ws_url_from_version(response: &str) -> Option<String> {
+    let body = response
+        .split_once("\r\n\r\n")
+        .or_else(|| response.split_once("\n\n"))
+        .map_or(response, |(_, body)| body);
+    let version: serde_json::Value = serde_json::from_str(body).ok()?;
+    version["webSocketDebuggerUrl"]
+        .as_str()
+        .map(|url| url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::saml::entra_id::EntraIdSamlIdProvider;
+
+    #[test]
+    fn test_wipe_cookies() {
+        //Arrange
+        let dir = std::env::temp_dir().join(format!("awsaml-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("Default/Network")).unwrap();
+        fs::create_dir_all(dir.join("other")).unwrap();
+        for file in [
+            "Default/Network/Cookies",
+            "Default/Preferences",
+            "other/Cookies",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        //Act
+        let removed = wipe_cookies(&dir).unwrap();
+        //Assert
+        assert_eq!(removed, vec![dir.join("Default/Network/Cookies")]);
+        assert!(dir.join("Default/Preferences").exists());
+        assert!(dir.join("other/Cookies").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wipe_cookies_singleton_lock() {
+        //Arrange
+        let dir = std::env::temp_dir().join(format!("awsaml-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("Default")).unwrap();
+        fs::write(dir.join("Default/Cookies"), "").unwrap();
+        let host = hostname().unwrap();
+        let lock = dir.join(SINGLETON_LOCK);
+        // left by a killed Chrome, so the symlink is dangling and the pid is gone
+        std::os::unix::fs::symlink(format!("{}-{}", host, i32::MAX), &lock).unwrap();
+        //Act
+        let stale = wipe_cookies(&dir);
+        fs::remove_file(&lock).unwrap();
+        std::os::unix::fs::symlink(format!("{}-{}", host, std::process::id()), &lock).unwrap();
+        let running = wipe_cookies(&dir);
+        //Assert
+        assert_eq!(stale.unwrap(), vec![dir.join("Default/Cookies")]);
+        assert!(matches!(running, Err(Error::Browser(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_debugger_ws_url_passthrough() {
+        let ws_url = "ws://127.0.0.1:9222/devtools/browser/abc";
+        assert_eq!(debugger_ws_url(ws_url).unwrap(), ws_url);
+    }
+
+    #[test]
+    fn test_parse_ws_url_from_version() {
+        //Arrange
+        let response = r#"HTTP/1.1 200 OK
+Content-Type: application/json; charset=UTF-8
+
+{
+   "Browser": "Chrome/131.0.6778.86",
+   "Protocol-Version": "1.3",
+   "webSocketDebuggerUrl": "ws://127.0.0.1:9222/devtools/browser/8f1e3f5e-3c6b-4a5e-9d3b-1c2d3e4f5a6b"
+}"#;
+        //Act
+        let result = parse_ws_url_from_version(response);
+        //Assert
+        assert_eq!(
+            result,
+            Some(
+                "ws://127.0.0.1:9222/devtools/browser/8f1e3f5e-3c6b-4a5e-9d3b-1c2d3e4f5a6b"
+                    .to_string()
+            )
+        );
+    }
+
+    /// build a SAML response with as many roles as `role_count`
+    fn saml_response_xml(role_count: usize) -> String {
+        let roles = (0..role_count)
+            .map(|i| {
+                format!(
+                    "<AttributeValue>arn:aws:iam::{:012}:role/Role{},arn:aws:iam::{:012}:saml-provider/EntraID</AttributeValue>",
+                    i, i, i
+                )
+            })
+            .collect::<String>();
+        format!(
+            r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"><Assertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><AttributeStatement><Attribute Name="https://aws.amazon.com/SAML/Attributes/Role">{}</Attribute></AttributeStatement></Assertion></samlp:Response>"#,
+            roles
+        )
+    }
+
+    /// split the form data into base64 encoded entries as Chrome does
+    fn post_data_entries(post_data: &str, chunk_size: usize) -> Vec<PostDataEntry> {
+        post_data
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(|chunk| PostDataEntry {
+                bytes: Some(BASE64_STANDARD.encode(chunk)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_saml_response_from_large_entries() {
+        //Arrange
+        let xml = saml_response_xml(20_000);
+        assert!(xml.len() > 2_000_000);
+        let post_data = form_urlencoded::Serializer::new(String::new())
+            .append_pair("SAMLResponse", &BASE64_STANDARD.encode(&xml))
+            .append_pair("RelayState", "")
+            .finish();
+        let entries = post_data_entries(&post_data, 65_536);
+        assert!(entries.len() > 1);
+        //Act
+        let concatenated = concat_post_data_entries(&entries).unwrap();
+        let result = ChromeSamlAgent::extract_saml_response(&concatenated, None).unwrap();
+        //Assert
+        assert_eq!(concatenated, post_data);
+        assert_eq!(result.as_xml(), xml);
+    }
+
+    #[test]
+    fn test_concat_raw_post_data_entries() {
+        //Arrange
+        let entries = vec![
+            PostDataEntry {
+                bytes: Some("SAMLResponse=abc%3D".to_string()),
+            },
+            PostDataEntry {
+                bytes: Some("&RelayState=".to_string()),
+            },
+        ];
+        //Act
+        let result = concat_post_data_entries(&entries);
+        //Assert
+        assert!(matches!(result, Err(Error::SamlDecode(_))));
+    }
+
+    #[test]
+    fn test_extract_saml_response_hundreds_of_roles() {
+        //Arrange
+        let xml = saml_response_xml(500);
+        let post_data = form_urlencoded::Serializer::new(String::new())
+            .append_pair("SAMLResponse", &BASE64_STANDARD.encode(&xml))
+            .finish();
+        //Act
+        let result = ChromeSamlAgent::extract_saml_response(&post_data, None);
+        //Assert
+        assert_eq!(result.unwrap().as_xml().matches(":role/Role").count(), 500);
+    }
+
+    #[test]
+    fn test_extract_saml_response_missing() {
+        let result = ChromeSamlAgent::extract_saml_response("RelayState=", None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_extract_logout_response() {
+        //Arrange
+        let xml = r#"<samlp:LogoutResponse xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" InResponseTo="id_1"><samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status></samlp:LogoutResponse>"#;
+        let url = format!(
+            "https://signin.aws.amazon.com/logout?SAMLResponse={}&RelayState=x",
+            EncodedSAML::from_raw_string(xml.to_string()).to_string()
+        );
+        //Act
+        let result = ChromeSamlAgent::extract_logout_response(&url).unwrap();
+        //Assert
+        assert_eq!(result.as_xml(), xml);
+        assert_eq!(result.in_response_to().unwrap().as_deref(), Some("id_1"));
+    }
+
+    #[test]
+    fn test_is_login_error() {
+        //Arrange
+        let idp = EntraIdSamlIdProvider::new("contoso.onmicrosoft.com".to_string());
+        let callback_url = "https://signin.aws.amazon.com/saml";
+        let document = ResourceType::Document;
+        //Act & Assert
+        // errors from the IdP login domain
+        assert!(is_login_error(
+            &idp,
+            callback_url,
+            &document,
+            400,
+            "https://login.microsoftonline.com/contoso.onmicrosoft.com/saml2"
+        ));
+        // errors from the SP callback
+        assert!(is_login_error(
+            &idp,
+            callback_url,
+            &document,
+            500,
+            callback_url
+        ));
+        // not an error
+        assert!(!is_login_error(
+            &idp,
+            callback_url,
+            &document,
+            302,
+            "https://login.microsoftonline.com/contoso.onmicrosoft.com/saml2"
+        ));
+        // not a document
+        assert!(!is_login_error(
+            &idp,
+            callback_url,
+            &ResourceType::Image,
+            404,
+            "https://login.microsoftonline.com/favicon.ico"
+        ));
+        // another domain
+        assert!(!is_login_error(
+            &idp,
+            callback_url,
+            &document,
+            404,
+            "https://example.com/probe"
+        ));
+        // allowed by the IdP
+        assert!(!is_login_error(
+            &idp,
+            callback_url,
+            &document,
+            401,
+            "https://login.microsoftonline.com/common/DeviceAuthTls/reprocess"
+        ));
+    }
 
-        Ok(SamlResponse::from_encoded(EncodedSAML(saml_response)))
+    #[test]
+    fn test_parse_ws_url_from_version_missing() {
+        assert_eq!(parse_ws_url_from_version("HTTP/1.1 404 Not Found"), None);
+        // only the top-level key counts, not the same key nested in another value
+        assert_eq!(
+            parse_ws_url_from_version(
+                "HTTP/1.1 200 OK\r\n\r\n{\"Extension\": {\"webSocketDebuggerUrl\": \"ws://other\"}}"
+            ),
+            None
+        );
     }
 }
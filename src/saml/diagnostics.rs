@@ -0,0 +1,384 @@
+//! Diagnostics bundle of a failed login.
+use super::EncodedSAML;
+use crate::atomic_file;
+use crate::{Error, Result};
+use chrono::{SecondsFormat, Utc};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use url::Url;
+
+/// Elements whose contents are personal or secret, and must not leave the machine
+const REDACTED_ELEMENTS: [&str; 6] = [
+    "NameID",
+    "AttributeValue",
+    "SignatureValue",
+    "DigestValue",
+    "X509Certificate",
+    "CipherValue",
+];
+const REDACTED: &str = "***REDACTED***";
+/// The inline script of the Entra ID pages holding the state of the login, such as the user name
+const CONFIG_SCRIPT: &str = "$Config";
+
+#[derive(Debug, Default)]
+struct Records {
+    timeline: Vec<String>,
+    authn_request: Option<String>,
+    saml_response: Option<String>,
+}
+
+/// Collects what happened during the login,
+/// and saves it into a directory when the login fails,
+/// so that it can be attached to a support ticket.
+#[derive(Debug)]
+pub struct DebugBundle {
+    dir: PathBuf,
+    records: Mutex<Records>,
+}
+
+impl DebugBundle {
+    pub fn new(dir: PathBuf) -> Self {
+        DebugBundle {
+            dir,
+            records: Mutex::new(Records::default()),
+        }
+    }
+
+    /// Record a document loaded in the browser.
+    /// The query is dropped because it may contain tokens.
+    pub(crate) fn record_navigation(&self, status: u32, url: &str) {
+        let url = match Url::parse(url) {
+            Ok(mut url) => {
+                if url.query().is_some() {
+                    url.set_query(Some("..."));
+                }
+                url.to_string()
+            }
+            Err(_) => url.to_string(),
+        };
+        let entry = format!("{} {} {}", now(), status, url);
+        if let Ok(mut records) = self.records.lock() {
+            records.timeline.push(entry);
+        }
+    }
+
+    pub(crate) fn record_authn_request(&self, xml: &str) {
+        if let Ok(mut records) = self.records.lock() {
+            records.authn_request = Some(redact_xml(xml));
+        }
+    }
+
+    /// Record the response as posted, before it is validated.
+    /// Only the reason is kept if it cannot be decoded.
+    pub(crate) fn record_saml_response(&self, encoded: &EncodedSAML) {
+        let saml_response = match encoded.to_raw_string() {
+            Ok(xml) => redact_xml(&xml),
+            Err(e) => format!("<!-- The SAMLResponse could not be decoded: {} -->\n", e),
+        };
+        if let Ok(mut records) = self.records.lock() {
+            records.saml_response = Some(saml_response);
+        }
+    }
+
+    /// Save the records with the error, the screenshot and the HTML of the page
+    /// of the login `attempt`, such as `silent`.
+    /// The files are readable only by the user, and the page has its form values redacted.
+    /// Returns the directory where they are saved.
+    pub(crate) fn save(
+        &self,
+        attempt: &str,
+        error: &Error,
+        screenshot: Option<Vec<u8>>,
+        page_html: Option<String>,
+    ) -> Result<PathBuf> {
+        let dir = self.dir.join(format!(
+            "awsaml-{}-{}",
+            Utc::now().format("%Y%m%dT%H%M%SZ"),
+            attempt
+        ));
+        fs::create_dir_all(&dir)?;
+
+        atomic_file::write(&dir.join("error.txt"), format!("{:?}\n", error).as_bytes())?;
+        if let Some(screenshot) = screenshot {
+            atomic_file::write(&dir.join("screenshot.png"), &screenshot)?;
+        }
+        if let Some(page_html) = page_html {
+            atomic_file::write(&dir.join("page.html"), redact_html(&page_html).as_bytes())?;
+        }
+
+        let records = self
+            .records
+            .lock()
+            .map_err(|_| Error::Browser("The debug bundle is poisoned".to_string()))?;
+        atomic_file::write(
+            &dir.join("timeline.txt"),
+            (records.timeline.join("\n") + "\n").as_bytes(),
+        )?;
+        if let Some(authn_request) = &records.authn_request {
+            atomic_file::write(&dir.join("authn_request.xml"), authn_request.as_bytes())?;
+        }
+        if let Some(saml_response) = &records.saml_response {
+            atomic_file::write(&dir.join("saml_response.xml"), saml_response.as_bytes())?;
+        }
+        Ok(dir)
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Replace the contents of the personal or secret elements with a placeholder
+pub fn redact_xml(xml: &str) -> String {
+    let mut redacted = String::with_capacity(xml.len());
+    let mut redacting: Option<&str> = None;
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        let text = &rest[..start];
+        match redacting {
+            Some(_) if !text.trim().is_empty() => redacted.push_str(REDACTED),
+            _ => redacted.push_str(text),
+        }
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = &rest[start..start + end + 1];
+        redacted.push_str(tag);
+        rest = &rest[start + end + 1..];
+
+        let name = tag
+            .trim_start_matches(['<', '/'])
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or("");
+        let local_name = name.rsplit(':').next().unwrap_or(name);
+        if tag.starts_with("</") {
+            if redacting == Some(local_name) {
+                redacting = None;
+            }
+        } else if !tag.ends_with("/>") && redacting.is_none() {
+            redacting = REDACTED_ELEMENTS
+                .iter()
+                .find(|element| **element == local_name)
+                .copied();
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Replace the values of the form inputs and the `$Config` script of the page with a placeholder,
+/// which may hold the user name and the tokens of the login
+pub fn redact_html(html: &str) -> String {
+    let mut redacted = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        redacted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let name = rest[1..]
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let end = if name == "input" {
+            tag_end(rest)
+        } else {
+            rest.find('>')
+        };
+        let Some(end) = end else {
+            break;
+        };
+        let tag = &rest[..end + 1];
+        rest = &rest[end + 1..];
+
+        match name.as_str() {
+            "input" => redacted.push_str(&redact_value(tag)),
+            "script" | "textarea" if !tag.ends_with("/>") => {
+                redacted.push_str(tag);
+                let content_end = rest
+                    .to_ascii_lowercase()
+                    .find(&format!("</{}", name))
+                    .unwrap_or(rest.len());
+                let content = &rest[..content_end];
+                if (name == "textarea" && !content.is_empty()) || content.contains(CONFIG_SCRIPT) {
+                    redacted.push_str(REDACTED);
+                } else {
+                    redacted.push_str(content);
+                }
+                rest = &rest[content_end..];
+            }
+            _ => redacted.push_str(tag),
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// The index of `>` closing the tag, skipping the quoted attribute values
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Replace the `value` attribute of the tag with a placeholder, keeping the others
+fn redact_value(tag: &str) -> String {
+    let mut redacted = String::with_capacity(tag.len());
+    let name_end = tag[1..]
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .map(|i| i + 1)
+        .unwrap_or(tag.len());
+    redacted.push_str(&tag[..name_end]);
+    let mut rest = &tag[name_end..];
+
+    loop {
+        let space = rest.len() - rest.trim_start().len();
+        redacted.push_str(&rest[..space]);
+        rest = &rest[space..];
+        if rest.is_empty() || rest.starts_with('>') || rest.starts_with("/>") {
+            break;
+        }
+        let attr_len = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>')
+            .unwrap_or(rest.len())
+            .max(1);
+        let attr = &rest[..attr_len];
+        redacted.push_str(attr);
+        rest = &rest[attr_len..];
+
+        let Some(after_eq) = rest.trim_start().strip_prefix('=') else {
+            continue;
+        };
+        let value_start = after_eq.len() - after_eq.trim_start().len();
+        redacted.push_str(&rest[..rest.len() - after_eq.len() + value_start]);
+        rest = &after_eq[value_start..];
+        let value_len = match rest.chars().next() {
+            Some(q @ ('"' | '\'')) => rest[1..].find(q).map(|i| i + 2).unwrap_or(rest.len()),
+            _ => rest
+                .find(|c: char| c.is_whitespace() || c == '>')
+                .unwrap_or(rest.len()),
+        };
+        let value = &rest[..value_len];
+        rest = &rest[value_len..];
+        if attr.eq_ignore_ascii_case("value") {
+            match value.chars().next() {
+                Some(q @ ('"' | '\'')) => {
+                    redacted.push(q);
+                    redacted.push_str(REDACTED);
+                    redacted.push(q);
+                }
+                _ => redacted.push_str(REDACTED),
+            }
+        } else {
+            redacted.push_str(value);
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::prelude::{Engine, BASE64_STANDARD};
+
+    #[test]
+    fn test_redact_xml() {
+        //Arrange
+        let xml = r#"<samlp:Response><Assertion><Subject><NameID Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">user@example.com</NameID></Subject><ds:Signature><ds:SignatureValue>c2VjcmV0</ds:SignatureValue></ds:Signature><AttributeStatement><Attribute Name="https://aws.amazon.com/SAML/Attributes/RoleSessionName"><AttributeValue>user@example.com</AttributeValue></Attribute></AttributeStatement></Assertion></samlp:Response>"#;
+        //Act
+        let result = redact_xml(xml);
+        //Assert
+        assert!(!result.contains("user@example.com"));
+        assert!(!result.contains("c2VjcmV0"));
+        assert!(result.contains(
+            r#"<Attribute Name="https://aws.amazon.com/SAML/Attributes/RoleSessionName">"#
+        ));
+        assert_eq!(result.matches(REDACTED).count(), 3);
+    }
+
+    #[test]
+    fn test_save_rejected_saml_response() {
+        //Arrange
+        let dir = std::env::temp_dir().join(format!("awsaml-{}", uuid::Uuid::new_v4()));
+        let bundle = DebugBundle::new(dir.clone());
+        let rejected = r#"<html><NameID>user@example.com</NameID></html>"#;
+        bundle.record_saml_response(
+            &EncodedSAML::from_input(&BASE64_STANDARD.encode(rejected)).unwrap(),
+        );
+        //Act
+        let saved = bundle
+            .save(
+                "silent",
+                &Error::SamlValidation("rejected".to_string()),
+                None,
+                Some(r#"<input name="loginfmt" value="user@example.com">"#.to_string()),
+            )
+            .unwrap();
+        //Assert
+        assert_eq!(saved.parent(), Some(dir.as_path()));
+        assert!(saved.to_string_lossy().ends_with("-silent"));
+        let saml_response = fs::read_to_string(saved.join("saml_response.xml")).unwrap();
+        assert_eq!(
+            saml_response,
+            format!("<html><NameID>{}</NameID></html>", REDACTED)
+        );
+        let page_html = fs::read_to_string(saved.join("page.html")).unwrap();
+        assert!(!page_html.contains("user@example.com"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(saved.join("page.html"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_redact_html() {
+        //Arrange
+        let html = r#"<form><input type="hidden" name="flowToken" value="AQABAAEAAAD"><input name=loginfmt value=user@example.com /><INPUT Value='a>b' id="x"><input type="submit" value="Sign in" disabled></form><script>//<![CDATA[
+$Config={"sPOST_Username":"user@example.com"};
+//]]></script><script src="/app.js"></script><script>var x = 1;</script>"#;
+        //Act
+        let result = redact_html(html);
+        //Assert
+        assert!(!result.contains("AQABAAEAAAD"));
+        assert!(!result.contains("user@example.com"));
+        assert!(!result.contains("a>b"));
+        assert!(result.contains(&format!(
+            r#"<input type="hidden" name="flowToken" value="{}">"#,
+            REDACTED
+        )));
+        assert!(result.contains(&format!("<input name=loginfmt value={} />", REDACTED)));
+        assert!(result.contains(&format!(r#"<INPUT Value='{}' id="x">"#, REDACTED)));
+        assert!(result.contains(" disabled></form>"));
+        assert!(result.contains(&format!("<script>{}</script>", REDACTED)));
+        assert!(result.contains(r#"<script src="/app.js"></script><script>var x = 1;</script>"#));
+    }
+
+    #[test]
+    fn test_redact_xml_keeps_others() {
+        //Arrange
+        let xml = r#"<samlp:AuthnRequest ID="id_1"><saml:Issuer>https://example.com/</saml:Issuer><samlp:NameIDPolicy Format="x" /></samlp:AuthnRequest>"#;
+        //Act
+        let result = redact_xml(xml);
+        //Assert
+        assert_eq!(result, xml);
+    }
+}
@@ -2,6 +2,11 @@ use url::Url;
 
 use super::SamlIdProvider;
 
+/// Paths of `login.microsoftonline.com` which legitimately answer 401/404 during the login,
+/// such as the device authentication of the conditional access.
+/// The other hosts are never taken for the error pages of the login.
+const BENIGN_ERROR_PATHS: [&str; 2] = ["/common/DeviceAuthTls/", "/favicon.ico"];
+
 #[derive(Debug)]
 pub struct EntraIdSamlIdProvider {
     tenant_id: String,
@@ -21,4 +26,89 @@ impl SamlIdProvider for EntraIdSamlIdProvider {
         ))
         .unwrap()
     }
+
+    /// Entra ID receives the LogoutRequest at the same endpoint as the AuthnRequest
+    fn logout_url(&self) -> Option<Url> {
+        Some(self.request_base())
+    }
+
+    fn interactive_login_selector(&self) -> Option<&str> {
+        // the account picker, the user name and the password inputs
+        Some("#tilesHolder, input[name=loginfmt], input[name=passwd]")
+    }
+
+    fn is_benign_error_response(&self, url: &Url, status: u32) -> bool {
+        if !matches!(status, 401 | 404) {
+            return false;
+        }
+        BENIGN_ERROR_PATHS
+            .iter()
+            .any(|path| url.path().starts_with(path))
+    }
+
+    /// Extract the `AADSTS` error code and its message,
+    /// e.g. "AADSTS50105: Your administrator has configured the application ..."
+    fn describe_error_page(&self, html: &str) -> Option<String> {
+        let start = html.find("AADSTS")?;
+        let code_len = html[start + "AADSTS".len()..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if code_len == 0 {
+            return None;
+        }
+        // the message ends at the end of the JSON string or the HTML element
+        let message = &html[start..];
+        let end = message
+            .char_indices()
+            .find(|&(i, c)| (c == '"' && !message[..i].ends_with('\\')) || c == '<' || c == '\n')
+            .map(|(i, _)| i)
+            .unwrap_or(message.len());
+        let message = message[..end]
+            .replace("\\u0027", "'")
+            .replace("\\u0026", "&")
+            .replace("\\\"", "\"")
+            .replace("\\r", "")
+            .replace("\\n", " ");
+        Some(message.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_error_page() {
+        //Arrange
+        let idp = EntraIdSamlIdProvider::new("contoso.onmicrosoft.com".to_string());
+        let html = r#"<script>$Config={"iErrorCode":50105,"strServiceExceptionMessage":"AADSTS50105: Your administrator has configured the application AWS ('b6a0c2d4') to block users unless they are specifically granted ('assigned') access to the application.\r\nTrace ID: 0f4e","sErrTxt":""};</script>"#;
+        //Act
+        let result = idp.describe_error_page(html);
+        //Assert
+        assert_eq!(
+            result,
+            Some("AADSTS50105: Your administrator has configured the application AWS ('b6a0c2d4') to block users unless they are specifically granted ('assigned') access to the application. Trace ID: 0f4e".to_string())
+        );
+    }
+
+    #[test]
+    fn test_describe_error_page_without_code() {
+        let idp = EntraIdSamlIdProvider::new("contoso.onmicrosoft.com".to_string());
+        assert_eq!(idp.describe_error_page("<html>Not Found</html>"), None);
+    }
+
+    #[test]
+    fn test_is_benign_error_response() -> anyhow::Result<()> {
+        let idp = EntraIdSamlIdProvider::new("contoso.onmicrosoft.com".to_string());
+        assert!(idp.is_benign_error_response(
+            &Url::parse("https://login.microsoftonline.com/common/DeviceAuthTls/reprocess")?,
+            401
+        ));
+        assert!(!idp.is_benign_error_response(
+            &Url::parse("https://login.microsoftonline.com/contoso.onmicrosoft.com/saml2")?,
+            400
+        ));
+        Ok(())
+    }
 }
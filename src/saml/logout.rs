@@ -0,0 +1,301 @@
+//! Module for handling the SAML single logout
+use chrono::{DateTime, SecondsFormat, Utc};
+use url::Url;
+use uuid::Uuid;
+
+use super::res::evaluate_strings;
+use super::{EncodedSAML, RawXMLString, SamlResponse};
+use crate::{Error, IdpError, Result};
+
+/// The status code of a successful request
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+/// The top-level `Status` of the response, not the nested ones
+const STATUS_XPATH: &str = "/*[local-name()='LogoutResponse']/*[local-name()='Status']";
+
+/// A request to end the IdP session in which an assertion was issued,
+/// sent by the HTTP-Redirect binding
+#[derive(Debug, Clone)]
+pub struct SamlLogoutRequest {
+    pub id: Uuid,
+    pub instant: DateTime<Utc>,
+    pub app_id_uri: String,
+    pub destination: Url,
+    pub name_id: String,
+    pub name_id_format: Option<String>,
+    pub session_index: Option<String>,
+}
+
+impl SamlLogoutRequest {
+    /// The request for the subject and the session of the assertion
+    pub fn new(app_id_uri: Url, destination: Url, saml_response: &SamlResponse) -> Result<Self> {
+        let name_id = saml_response.name_id()?.ok_or(Error::SamlValidation(
+            "No NameID in the assertion".to_string(),
+        ))?;
+        Ok(SamlLogoutRequest {
+            id: Uuid::new_v4(),
+            instant: Utc::now(),
+            app_id_uri: app_id_uri.to_string(),
+            destination,
+            name_id,
+            name_id_format: saml_response.name_id_format()?,
+            session_index: saml_response.session_index()?,
+        })
+    }
+
+    /// The `ID` of the request, which the response refers to by `InResponseTo`
+    pub fn request_id(&self) -> String {
+        format!("id_{}", self.id)
+    }
+
+    fn to_xml(&self) -> String {
+        let format = self
+            .name_id_format
+            .as_ref()
+            .map(|format| format!(r#" Format="{}""#, escape(format)))
+            .unwrap_or_default();
+        let session_index = self
+            .session_index
+            .as_ref()
+            .map(|index| format!("<samlp:SessionIndex>{}</samlp:SessionIndex>", escape(index)))
+            .unwrap_or_default();
+        format!(
+            r#"
+            <samlp:LogoutRequest
+                Destination="{destination}"
+                ID="{id}"
+                IssueInstant="{timestamp}"
+                Version="2.0"
+                xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"
+                xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion"
+            >
+                <saml:Issuer>{req_issuer}</saml:Issuer>
+                <saml:NameID{format}>{name_id}</saml:NameID>
+                {session_index}
+            </samlp:LogoutRequest>"#,
+            destination = escape(self.destination.as_str()),
+            id = self.request_id(),
+            timestamp = self.instant.to_rfc3339_opts(SecondsFormat::Millis, true),
+            req_issuer = escape(&self.app_id_uri),
+            format = format,
+            name_id = escape(&self.name_id),
+            session_index = session_index,
+        )
+    }
+
+    pub fn to_encoded_saml(&self) -> EncodedSAML {
+        let xml = self.to_xml();
+        log::debug!("SAML LogoutRequest: {}", xml);
+        EncodedSAML::from_raw_string(xml)
+    }
+
+    /// The URL which delivers the request to the destination
+    pub fn redirect_url(&self) -> Url {
+        let mut url = self.destination.clone();
+        url.set_query(Some(&format!(
+            "SAMLRequest={}",
+            self.to_encoded_saml().to_string()
+        )));
+        url
+    }
+}
+
+/// The answer of the IdP to a [`SamlLogoutRequest`]
+#[derive(Debug)]
+pub struct SamlLogoutResponse {
+    xml: RawXMLString,
+}
+
+impl SamlLogoutResponse {
+    pub fn from_encoded(encoded: EncodedSAML) -> Result<Self> {
+        let xml = encoded.to_raw_string()?;
+        log::debug!("SAML LogoutResponse: {}", xml);
+        if !xml.contains("urn:oasis:names:tc:SAML:2.0:protocol") || !xml.contains("LogoutResponse")
+        {
+            return Err(Error::SamlValidation(
+                "The value is not a SAML logout response".to_string(),
+            ));
+        }
+        Ok(SamlLogoutResponse { xml })
+    }
+
+    /// The decoded XML of the response
+    pub fn as_xml(&self) -> &str {
+        &self.xml
+    }
+
+    /// The `ID` of the request the response answers
+    pub fn in_response_to(&self) -> Result<Option<String>> {
+        Ok(
+            evaluate_strings(&self.xml, "/*[local-name()='LogoutResponse']/@InResponseTo")?
+                .into_iter()
+                .next(),
+        )
+    }
+
+    /// The top-level status code, e.g. `urn:oasis:names:tc:SAML:2.0:status:Success`
+    pub fn status_code(&self) -> Result<Option<String>> {
+        let xpath = format!("{}/*[local-name()='StatusCode']/@Value", STATUS_XPATH);
+        Ok(evaluate_strings(&self.xml, &xpath)?.into_iter().next())
+    }
+
+    /// The explanation of the status given by the IdP, if any
+    pub fn status_message(&self) -> Result<Option<String>> {
+        let xpath = format!("{}/*[local-name()='StatusMessage']", STATUS_XPATH);
+        Ok(evaluate_strings(&self.xml, &xpath)?.into_iter().next())
+    }
+
+    /// Check that the response answers the request, and that the IdP ended the session
+    pub fn verify(&self, logout_req: &SamlLogoutRequest) -> Result<()> {
+        let in_response_to = self.in_response_to()?;
+        if in_response_to.as_deref() != Some(logout_req.request_id().as_str()) {
+            return Err(Error::SamlValidation(format!(
+                "The LogoutResponse answers {:?} instead of {}",
+                in_response_to,
+                logout_req.request_id()
+            )));
+        }
+        let status = self.status_code()?.ok_or(Error::SamlValidation(
+            "No StatusCode in the LogoutResponse".to_string(),
+        ))?;
+        if status != STATUS_SUCCESS {
+            return Err(IdpError::LogoutFailed {
+                status,
+                message: self.status_message()?,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Escape the values which come from the assertion or the configuration
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::prelude::{Engine, BASE64_STANDARD};
+
+    const SAML_RESPONSE: &str = r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"><Assertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><Subject><NameID Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">user@example.com</NameID></Subject><AuthnStatement SessionIndex="_0123abcd"/></Assertion></samlp:Response>"#;
+
+    fn logout_request() -> SamlLogoutRequest {
+        let saml_res =
+            SamlResponse::from_encoded(EncodedSAML(BASE64_STANDARD.encode(SAML_RESPONSE))).unwrap();
+        SamlLogoutRequest::new(
+            Url::parse("https://signin.aws.amazon.com/saml#1").unwrap(),
+            Url::parse("https://login.microsoftonline.com/tenant/saml2").unwrap(),
+            &saml_res,
+        )
+        .unwrap()
+    }
+
+    fn logout_response(in_response_to: &str, status: &str) -> SamlLogoutResponse {
+        let xml = format!(
+            r#"<samlp:LogoutResponse xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" ID="_r" InResponseTo="{}" Version="2.0"><samlp:Status><samlp:StatusCode Value="{}"><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:PartialLogout"/></samlp:StatusCode><samlp:StatusMessage>Session not found</samlp:StatusMessage></samlp:Status></samlp:LogoutResponse>"#,
+            in_response_to, status
+        );
+        SamlLogoutResponse::from_encoded(EncodedSAML::from_raw_string(xml)).unwrap()
+    }
+
+    #[test]
+    fn test_logout_request_to_xml() {
+        //Arrange
+        let target = logout_request();
+        //Act
+        let xml = target.to_xml();
+        //Assert
+        let select = |xpath: &str| evaluate_strings(&xml, xpath).unwrap();
+        assert_eq!(
+            select("/*[local-name()='LogoutRequest']/@ID"),
+            vec![target.request_id()]
+        );
+        assert_eq!(
+            select("/*[local-name()='LogoutRequest']/@Destination"),
+            vec!["https://login.microsoftonline.com/tenant/saml2"]
+        );
+        assert_eq!(
+            select("//*[local-name()='Issuer']"),
+            vec!["https://signin.aws.amazon.com/saml#1"]
+        );
+        assert_eq!(
+            select("//*[local-name()='NameID']"),
+            vec!["user@example.com"]
+        );
+        assert_eq!(
+            select("//*[local-name()='NameID']/@Format"),
+            vec!["urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress"]
+        );
+        assert_eq!(
+            select("//*[local-name()='SessionIndex']"),
+            vec!["_0123abcd"]
+        );
+    }
+
+    #[test]
+    fn test_logout_request_to_xml_escapes_issuer() {
+        //Arrange
+        let target = SamlLogoutRequest {
+            app_id_uri: "https://example.com/saml?a=1&b=<2>".to_string(),
+            ..logout_request()
+        };
+        //Act
+        let xml = target.to_xml();
+        //Assert
+        assert_eq!(
+            evaluate_strings(&xml, "//*[local-name()='Issuer']").unwrap(),
+            vec!["https://example.com/saml?a=1&b=<2>"]
+        );
+    }
+
+    #[test]
+    fn test_redirect_url() {
+        //Arrange
+        let target = logout_request();
+        //Act
+        let url = target.redirect_url();
+        //Assert
+        let (key, value) = url.query_pairs().next().unwrap();
+        assert_eq!(key, "SAMLRequest");
+        assert_eq!(
+            EncodedSAML(value.to_string()).to_raw_string().unwrap(),
+            target.to_xml()
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        //Arrange
+        let logout_req = logout_request();
+        let success = logout_response(&logout_req.request_id(), STATUS_SUCCESS);
+        let requester = logout_response(
+            &logout_req.request_id(),
+            "urn:oasis:names:tc:SAML:2.0:status:Requester",
+        );
+        let other = logout_response("id_other", STATUS_SUCCESS);
+        //Act & Assert
+        assert!(success.verify(&logout_req).is_ok());
+        assert!(matches!(
+            requester.verify(&logout_req),
+            Err(Error::Idp(IdpError::LogoutFailed { status, message }))
+                if status == "urn:oasis:names:tc:SAML:2.0:status:Requester"
+                    && message.as_deref() == Some("Session not found")
+        ));
+        assert!(matches!(
+            other.verify(&logout_req),
+            Err(Error::SamlValidation(_))
+        ));
+    }
+
+    #[test]
+    fn test_logout_response_not_logout() {
+        let result =
+            SamlLogoutResponse::from_encoded(EncodedSAML(BASE64_STANDARD.encode(SAML_RESPONSE)));
+        assert!(matches!(result, Err(Error::SamlValidation(_))));
+    }
+}
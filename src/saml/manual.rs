@@ -0,0 +1,81 @@
+//! Manual SAML support.
+use super::{EncodedSAML, SamlAgent, SamlAuthRequest, SamlIdProvider, SamlResponse};
+use crate::Result;
+use async_trait::async_trait;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::PathBuf;
+
+/// Where the agent reads the SAMLResponse from
+#[derive(Debug, Clone)]
+pub enum SamlResponseSource {
+    /// Pasted by the user in the terminal
+    Prompt,
+    /// Piped into the standard input
+    Stdin,
+    /// Saved in a file
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+/// An agent that lets the user sign in with any browser,
+/// and receives the SAMLResponse copied from the browser dev tools.
+pub struct ManualSamlAgent {
+    idp: Box<dyn SamlIdProvider>,
+    source: SamlResponseSource,
+}
+
+impl ManualSamlAgent {
+    pub fn new(idp: Box<dyn SamlIdProvider>, source: SamlResponseSource) -> Self {
+        ManualSamlAgent { idp, source }
+    }
+}
+
+impl SamlResponseSource {
+    fn read(&self) -> Result<String> {
+        match self {
+            SamlResponseSource::Prompt => {
+                eprintln!("Paste the SAMLResponse, then press Enter on an empty line:");
+                let mut input = String::new();
+                for line in io::stdin().lock().lines() {
+                    let line = line?;
+                    if line.trim().is_empty() && !input.is_empty() {
+                        break;
+                    }
+                    input.push_str(&line);
+                }
+                Ok(input)
+            }
+            SamlResponseSource::Stdin => {
+                let mut input = String::new();
+                io::stdin().read_to_string(&mut input)?;
+                Ok(input)
+            }
+            SamlResponseSource::File(path) => Ok(fs::read_to_string(path)?),
+        }
+    }
+}
+
+#[async_trait]
+impl SamlAgent for ManualSamlAgent {
+    /// Print the login URL of the IdP,
+    /// and wait for the SAMLResponse posted to the SP callback.
+    async fn saml_request_to_idp(&mut self, saml_req: SamlAuthRequest) -> Result<SamlResponse> {
+        let callback_to = saml_req.callback_to.clone();
+        let url = self.idp.request_url(saml_req);
+        eprintln!("Open the following URL in your browser and sign in:");
+        eprintln!();
+        eprintln!("{}", url);
+        eprintln!();
+        eprintln!(
+            "After signing in, copy the `SAMLResponse` form data of the POST request to {} from the Network tab of the dev tools.",
+            callback_to
+        );
+        // reading the terminal blocks, so keep it off the executor threads
+        let source = self.source.clone();
+        let input = tokio::task::spawn_blocking(move || source.read())
+            .await
+            .map_err(io::Error::other)??;
+        SamlResponse::from_encoded(EncodedSAML::from_input(&input)?)
+    }
+}
@@ -1,12 +1,308 @@
-use super::EncodedSAML;
+use super::{EncodedSAML, RawXMLString};
+use crate::{Error, Result};
+use std::time::Duration;
+use sxd_xpath::Value;
+
+/// The attribute listing the roles the user may assume, as `role ARN,principal ARN`
+const ROLE_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/Role";
+/// The attribute capping the session duration in seconds
+const SESSION_DURATION_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/SessionDuration";
+/// The attribute naming the session of the assumed role
+const ROLE_SESSION_NAME_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/RoleSessionName";
+/// The prefix of the attributes passed as the session tags, followed by the tag key
+const PRINCIPAL_TAG_ATTRIBUTE_PREFIX: &str = "https://aws.amazon.com/SAML/Attributes/PrincipalTag:";
+/// The attribute listing the session tags which persist into the chained roles
+const TRANSITIVE_TAG_KEYS_ATTRIBUTE: &str =
+    "https://aws.amazon.com/SAML/Attributes/TransitiveTagKeys";
+
+/// The `NameID` of the subject of the assertion
+const NAME_ID_XPATH: &str = "//*[local-name()='Subject']/*[local-name()='NameID']";
 
 #[derive(Debug)]
-pub struct SamlResponse {}
+pub struct SamlResponse {
+    encoded: EncodedSAML,
+    xml: RawXMLString,
+}
+
+/// An IAM role granted by the assertion, with the SAML provider trusted by the role
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamlRole {
+    pub role_arn: String,
+    pub principal_arn: String,
+}
+
+/// A tag of the role session, used by the ABAC policies as `aws:PrincipalTag/<key>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionTag {
+    pub key: String,
+    pub value: String,
+}
+
+impl SamlRole {
+    /// Parse an attribute value, in which the role and the provider may be in either order
+    fn parse(value: &str) -> Option<Self> {
+        let (first, second) = value.trim().split_once(',')?;
+        let (first, second) = (first.trim(), second.trim());
+        if first.contains(":saml-provider/") {
+            Some(SamlRole {
+                role_arn: second.to_string(),
+                principal_arn: first.to_string(),
+            })
+        } else if second.contains(":saml-provider/") {
+            Some(SamlRole {
+                role_arn: first.to_string(),
+                principal_arn: second.to_string(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The role name without the path, e.g. `Admin` of `arn:aws:iam::123456789012:role/path/Admin`
+    pub fn name(&self) -> &str {
+        self.role_arn.rsplit('/').next().unwrap_or(&self.role_arn)
+    }
+}
 
 impl SamlResponse {
-    pub fn from_encoded(encoded: EncodedSAML) -> Self {
-        let raw_string = encoded.to_raw_string();
-        println!("{:?}", raw_string);
-        SamlResponse {}
+    pub fn from_encoded(encoded: EncodedSAML) -> Result<Self> {
+        let xml = encoded.to_raw_string()?;
+        log::debug!("SAML Response: {}", xml);
+        if !xml.contains("urn:oasis:names:tc:SAML:2.0:protocol") || !xml.contains("Response") {
+            return Err(Error::SamlValidation(
+                "The value is not a SAML response".to_string(),
+            ));
+        }
+        Ok(SamlResponse { encoded, xml })
+    }
+
+    /// The base64 encoded response to be passed to `AssumeRoleWithSAML`
+    pub fn to_base64(&self) -> Result<String> {
+        self.encoded.to_base64()
+    }
+
+    /// The decoded XML of the response
+    pub fn as_xml(&self) -> &str {
+        &self.xml
+    }
+
+    /// The values of the attribute in the assertion, in the document order
+    pub fn attribute_values(&self, name: &str) -> Result<Vec<String>> {
+        let xpath = format!(
+            "//*[local-name()='Attribute'][@Name='{}']/*[local-name()='AttributeValue']",
+            name
+        );
+        evaluate_strings(&self.xml, &xpath)
+    }
+
+    /// The names of the attributes starting with `prefix`, in the document order
+    fn attribute_names(&self, prefix: &str) -> Result<Vec<String>> {
+        let xpath = format!(
+            "//*[local-name()='Attribute'][starts-with(@Name, '{}')]/@Name",
+            prefix
+        );
+        evaluate_strings(&self.xml, &xpath)
+    }
+
+    /// The `NameID` of the subject, which the single logout names
+    pub fn name_id(&self) -> Result<Option<String>> {
+        Ok(evaluate_strings(&self.xml, NAME_ID_XPATH)?
+            .into_iter()
+            .next())
+    }
+
+    /// The `Format` of the `NameID`, if given
+    pub fn name_id_format(&self) -> Result<Option<String>> {
+        let xpath = format!("{}/@Format", NAME_ID_XPATH);
+        Ok(evaluate_strings(&self.xml, &xpath)?.into_iter().next())
+    }
+
+    /// The `SessionIndex` of the authentication, identifying the IdP session to end
+    pub fn session_index(&self) -> Result<Option<String>> {
+        Ok(evaluate_strings(
+            &self.xml,
+            "//*[local-name()='AuthnStatement']/@SessionIndex",
+        )?
+        .into_iter()
+        .next())
+    }
+
+    /// The name of the role session given by the IdP, usually the user principal name
+    pub fn role_session_name(&self) -> Result<Option<String>> {
+        Ok(self
+            .attribute_values(ROLE_SESSION_NAME_ATTRIBUTE)?
+            .into_iter()
+            .next())
+    }
+
+    /// The longest session allowed by the IdP, `None` if unlimited
+    pub fn session_duration(&self) -> Result<Option<Duration>> {
+        self.attribute_values(SESSION_DURATION_ATTRIBUTE)?
+            .first()
+            .map(|value| {
+                value.parse::<u64>().map(Duration::from_secs).map_err(|e| {
+                    Error::SamlValidation(format!(
+                        "Invalid {}: {}: {}",
+                        SESSION_DURATION_ATTRIBUTE, value, e
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    /// The session tags passed by the `PrincipalTag:<key>` attributes
+    pub fn session_tags(&self) -> Result<Vec<SessionTag>> {
+        let mut tags = Vec::new();
+        for name in self.attribute_names(PRINCIPAL_TAG_ATTRIBUTE_PREFIX)? {
+            // a session tag has a single value
+            if let Some(value) = self.attribute_values(&name)?.into_iter().next() {
+                tags.push(SessionTag {
+                    key: name[PRINCIPAL_TAG_ATTRIBUTE_PREFIX.len()..].to_string(),
+                    value,
+                });
+            }
+        }
+        Ok(tags)
+    }
+
+    /// The keys of the session tags which persist into the chained roles
+    pub fn transitive_tag_keys(&self) -> Result<Vec<String>> {
+        self.attribute_values(TRANSITIVE_TAG_KEYS_ATTRIBUTE)
+    }
+
+    /// The roles the user may assume
+    pub fn roles(&self) -> Result<Vec<SamlRole>> {
+        let roles = self
+            .attribute_values(ROLE_ATTRIBUTE)?
+            .iter()
+            .filter_map(|value| SamlRole::parse(value))
+            .collect::<Vec<_>>();
+        if roles.is_empty() {
+            return Err(Error::SamlValidation(format!(
+                "No role is granted in the {} attribute",
+                ROLE_ATTRIBUTE
+            )));
+        }
+        Ok(roles)
+    }
+}
+
+/// The string values of the nodes selected by `xpath`, trimmed and in the document order
+pub(super) fn evaluate_strings(xml: &str, xpath: &str) -> Result<Vec<String>> {
+    let package = sxd_document::parser::parse(xml).map_err(Error::saml_decode)?;
+    let document = package.as_document();
+    match sxd_xpath::evaluate_xpath(&document, xpath).map_err(Error::saml_decode)? {
+        Value::Nodeset(nodes) => Ok(nodes
+            .document_order()
+            .iter()
+            .map(|node| node.string_value().trim().to_string())
+            .collect()),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::prelude::{Engine, BASE64_STANDARD};
+
+    const SAML_RESPONSE: &str = r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"><Assertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><AttributeStatement><Attribute Name="https://aws.amazon.com/SAML/Attributes/Role"><AttributeValue>arn:aws:iam::123456789012:role/path/Admin,arn:aws:iam::123456789012:saml-provider/EntraID</AttributeValue><AttributeValue>arn:aws:iam::123456789012:saml-provider/EntraID,arn:aws:iam::123456789012:role/ReadOnly</AttributeValue></Attribute><Attribute Name="https://aws.amazon.com/SAML/Attributes/RoleSessionName"><AttributeValue>user@example.com</AttributeValue></Attribute></AttributeStatement></Assertion></samlp:Response>"#;
+
+    fn saml_response(xml: &str) -> SamlResponse {
+        SamlResponse::from_encoded(EncodedSAML(BASE64_STANDARD.encode(xml))).unwrap()
+    }
+
+    #[test]
+    fn test_roles() {
+        //Arrange
+        let target = saml_response(SAML_RESPONSE);
+        //Act
+        let result = target.roles().unwrap();
+        //Assert
+        assert_eq!(
+            result,
+            vec![
+                SamlRole {
+                    role_arn: "arn:aws:iam::123456789012:role/path/Admin".to_string(),
+                    principal_arn: "arn:aws:iam::123456789012:saml-provider/EntraID".to_string(),
+                },
+                SamlRole {
+                    role_arn: "arn:aws:iam::123456789012:role/ReadOnly".to_string(),
+                    principal_arn: "arn:aws:iam::123456789012:saml-provider/EntraID".to_string(),
+                },
+            ]
+        );
+        assert_eq!(result[0].name(), "Admin");
+    }
+
+    #[test]
+    fn test_session_duration() {
+        //Arrange
+        let target = saml_response(
+            r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"><Assertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><AttributeStatement><Attribute Name="https://aws.amazon.com/SAML/Attributes/SessionDuration"><AttributeValue>7200</AttributeValue></Attribute></AttributeStatement></Assertion></samlp:Response>"#,
+        );
+        //Act
+        let result = target.session_duration().unwrap();
+        //Assert
+        assert_eq!(result, Some(Duration::from_secs(7200)));
+        assert_eq!(
+            saml_response(SAML_RESPONSE).session_duration().unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_session_tags() {
+        //Arrange
+        let target = saml_response(
+            r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"><Assertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><AttributeStatement><Attribute Name="https://aws.amazon.com/SAML/Attributes/PrincipalTag:Project"><AttributeValue>alpha</AttributeValue></Attribute><Attribute Name="https://aws.amazon.com/SAML/Attributes/PrincipalTag:CostCenter"><AttributeValue>42</AttributeValue></Attribute><Attribute Name="https://aws.amazon.com/SAML/Attributes/TransitiveTagKeys"><AttributeValue>Project</AttributeValue></Attribute></AttributeStatement></Assertion></samlp:Response>"#,
+        );
+        //Act
+        let tags = target.session_tags().unwrap();
+        let transitive_tag_keys = target.transitive_tag_keys().unwrap();
+        //Assert
+        assert_eq!(
+            tags,
+            vec![
+                SessionTag {
+                    key: "Project".to_string(),
+                    value: "alpha".to_string(),
+                },
+                SessionTag {
+                    key: "CostCenter".to_string(),
+                    value: "42".to_string(),
+                },
+            ]
+        );
+        assert_eq!(transitive_tag_keys, vec!["Project".to_string()]);
+        assert_eq!(target.role_session_name().unwrap(), None);
+    }
+
+    #[test]
+    fn test_subject() {
+        //Arrange
+        let target = saml_response(
+            r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"><Assertion xmlns="urn:oasis:names:tc:SAML:2.0:assertion"><Subject><NameID Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">user@example.com</NameID></Subject><AuthnStatement AuthnInstant="2024-01-01T00:00:00Z" SessionIndex="_0123abcd"/></Assertion></samlp:Response>"#,
+        );
+        //Act
+        let name_id = target.name_id().unwrap();
+        let name_id_format = target.name_id_format().unwrap();
+        let session_index = target.session_index().unwrap();
+        //Assert
+        assert_eq!(name_id.as_deref(), Some("user@example.com"));
+        assert_eq!(
+            name_id_format.as_deref(),
+            Some("urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress")
+        );
+        assert_eq!(session_index.as_deref(), Some("_0123abcd"));
+        assert_eq!(saml_response(SAML_RESPONSE).name_id().unwrap(), None);
+    }
+
+    #[test]
+    fn test_roles_missing() {
+        let target = saml_response(
+            r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol"/>"#,
+        );
+        assert!(matches!(target.roles(), Err(Error::SamlValidation(_))));
     }
 }